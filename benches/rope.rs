@@ -0,0 +1,110 @@
+// Text storage benchmark.
+// Times the line operations the editor does on every
+// keypress against a 100k line file, each one has to
+// stay well under a millisecond.
+//
+// cargo bench --bench rope
+
+#![allow(dead_code)]
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[path = "../src/rope.rs"]
+mod rope;
+use rope::Rope;
+
+// Lines in the benchmarked file
+const LINES: usize = 100_000;
+
+// Times each operation is repeated
+const RUNS: usize = 1_000;
+
+// Budget for a single operation
+const BUDGET: Duration = Duration::from_millis(1);
+
+// A file with LINES lines of something that looks like a log
+fn file() -> Rope {
+    Rope::from(
+        (0..LINES)
+            .map(|i| format!("2024-01-01 12:00:{:02} INFO request {} handled in {}ms", i % 60, i, i % 250))
+            .collect::<Vec<_>>()
+    )
+}
+
+// Run an operation RUNS times at a spread of lines, return the worst time
+fn bench(name: &str, rope: &mut Rope, operation: fn(&mut Rope, usize)) -> Duration {
+    let mut worst = Duration::ZERO;
+    let mut total = Duration::ZERO;
+
+    for run in 0..RUNS {
+        let line = (run * 7919) % (rope.len() - 1);
+
+        let start = Instant::now();
+        operation(rope, line);
+        let elapsed = start.elapsed();
+
+        worst = worst.max(elapsed);
+        total += elapsed;
+    }
+
+    println!("{:<24} avg {:>10.2?}   worst {:>10.2?}", name, total / RUNS as u32, worst);
+    worst
+}
+
+// Enter in the middle of a line
+fn split_line(rope: &mut Rope, line: usize) {
+    let half = rope[line].len() / 2;
+    let rest = rope[line].split_off(half);
+    rope.insert(line + 1, rest);
+}
+
+// Backspace at the start of a line
+fn merge_lines(rope: &mut Rope, line: usize) {
+    let removed = rope.remove(line + 1);
+    rope[line].push_str(&removed);
+}
+
+// Typing a character
+fn insert_char(rope: &mut Rope, line: usize) {
+    rope[line].insert(0, 'x');
+}
+
+// Moving the cursor to a line
+fn lookup(rope: &mut Rope, line: usize) {
+    black_box(rope.get(line));
+}
+
+// Pasting a few lines
+fn insert_lines(rope: &mut Rope, line: usize) {
+    for i in 0..10 {
+        rope.insert(line + i, String::from("pasted"));
+    }
+}
+
+// Deleting a few lines
+fn delete_lines(rope: &mut Rope, line: usize) {
+    black_box(rope.drain(line..line + 10));
+}
+
+fn main() {
+    let mut rope = file();
+    println!("{} lines, {} runs each\n", rope.len(), RUNS);
+
+    let worst = [
+        bench("split line", &mut rope, split_line),
+        bench("merge lines", &mut rope, merge_lines),
+        bench("insert char", &mut rope, insert_char),
+        bench("line lookup", &mut rope, lookup),
+        bench("insert 10 lines", &mut rope, insert_lines),
+        bench("delete 10 lines", &mut rope, delete_lines)
+    ];
+
+    let over = worst.iter().filter(|worst| **worst > BUDGET).count();
+    if over > 0 {
+        println!("\n{} operations went over the {:?} budget", over, BUDGET);
+        std::process::exit(1);
+    }
+
+    println!("\nall operations under {:?}", BUDGET);
+}
@@ -0,0 +1,192 @@
+use macroquad::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::files::FileError;
+use crate::palette::Palette;
+
+// Directory browser.
+// Lists a directory over the text, opened with a bare '?cd'.
+// Up/Down pick an entry, Enter goes into a directory or opens a file,
+// Backspace goes to the parent directory and Escape closes it.
+//
+// Moving around changes the working directory, so where you
+// end up is where commands look for files afterwards.
+// Hidden entries are left out
+
+// An entry of the listed directory
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub directory: bool
+}
+
+pub struct Browser {
+    pub directory: PathBuf,     // Directory listed
+    pub entries: Vec<Entry>,    // Directories first, then files, by name
+    pub selected: usize,        // Entry the cursor is on
+    top: usize                  // First entry on screen
+}
+
+impl Browser {
+
+    // Browser listing a directory
+    pub fn open(directory: &Path) -> Result<Self, FileError> {
+        let mut browser = Self {
+            directory: PathBuf::new(),
+            entries: Vec::new(),
+            selected: 0,
+            top: 0
+        };
+
+        browser.list(directory)?;
+        Ok(browser)
+    }
+
+    // List another directory, selecting its first entry
+    fn list(&mut self, directory: &Path) -> Result<(), FileError> {
+        let directory = directory.canonicalize().map_err(|e| FileError::from_io(directory, e))?;
+
+        let mut entries: Vec<Entry> = fs::read_dir(&directory)
+            .map_err(|e| FileError::from_io(&directory, e))?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if name.starts_with('.') {
+                    return None;
+                }
+
+                Some(Entry { name, directory: entry.path().is_dir() })
+            })
+            .collect();
+
+        entries.sort_by(|a, b| b.directory.cmp(&a.directory).then_with(|| a.name.cmp(&b.name)));
+
+        self.directory = directory;
+        self.entries = entries;
+        self.selected = 0;
+        self.top = 0;
+
+        Ok(())
+    }
+
+    // Select the previous entry
+    pub fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // Select the next entry
+    pub fn down(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    // Go into the selected directory, making it the working directory.
+    // Returns the selected file's path when it is a file instead
+    pub fn enter(&mut self) -> Result<Option<PathBuf>, FileError> {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return Ok(None);
+        };
+
+        let path = self.directory.join(&entry.name);
+        if !entry.directory {
+            return Ok(Some(path));
+        }
+
+        self.change_to(&path)?;
+        Ok(None)
+    }
+
+    // Go to the parent directory, with the one we
+    // came from selected. Nothing happens at the root
+    pub fn parent(&mut self) -> Result<(), FileError> {
+        let Some(parent) = self.directory.parent().map(Path::to_path_buf) else {
+            return Ok(());
+        };
+        let came_from = self.directory.file_name().map(|name| name.to_string_lossy().into_owned());
+
+        self.change_to(&parent)?;
+
+        if let Some(index) = self.entries.iter().position(|entry| Some(&entry.name) == came_from.as_ref()) {
+            self.selected = index;
+        }
+
+        Ok(())
+    }
+
+    // List a directory and make it the working directory
+    fn change_to(&mut self, directory: &Path) -> Result<(), FileError> {
+        self.list(directory)?;
+        std::env::set_current_dir(&self.directory).map_err(|e| FileError::from_io(directory, e))
+    }
+
+    // Draw the listing over the text area, between 'top' and 'bottom'
+    pub fn render(&mut self, font: &Font, font_size: u16, top: f32, bottom: f32, palette: &Palette) {
+        let row_height = font_size as f32;
+        let rows = ((bottom - top - 5.0) / row_height).max(2.0) as usize - 1; // The first row is the header
+
+        // Keep the selected entry on screen
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+
+        draw_rectangle(0.0, top, screen_width(), bottom - top, palette.background);
+
+        let params = |color| TextParams {
+            font: Some(font),
+            font_size,
+            color,
+            ..Default::default()
+        };
+
+        draw_text_ex(&format!("{}  (Enter: open, Backspace: up, Escape: close)", self.directory.display()),
+            10.0, top + 5.0 + row_height * 0.7, params(palette.dim));
+
+        if self.entries.is_empty() {
+            draw_text_ex("(empty)", 10.0, top + 5.0 + row_height * 1.7, params(palette.dim));
+        }
+
+        for (row, (i, entry)) in self.entries.iter().enumerate().skip(self.top).take(rows).enumerate() {
+            let y = top + 5.0 + (row + 1) as f32 * row_height;
+
+            if i == self.selected {
+                draw_rectangle(0.0, y, screen_width(), row_height, palette.selection);
+            }
+
+            let label = if entry.directory { format!("{}/", entry.name) } else { entry.name.clone() };
+            draw_text_ex(&label, 10.0, y + row_height * 0.7, params(palette.text));
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_directories_first_without_hidden_entries() {
+        let directory = std::env::temp_dir().join(format!("whiskey-browser-{}", std::process::id()));
+        fs::create_dir_all(directory.join("src")).unwrap();
+        fs::write(directory.join("Cargo.toml"), "").unwrap();
+        fs::write(directory.join("a.txt"), "").unwrap();
+        fs::write(directory.join(".hidden"), "").unwrap();
+
+        let mut browser = Browser::open(&directory).unwrap();
+        let names: Vec<&str> = browser.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["src", "Cargo.toml", "a.txt"]);
+
+        browser.up();
+        assert_eq!(browser.selected, 0);
+        browser.down();
+        browser.down();
+        browser.down();
+        assert_eq!(browser.selected, 2);
+        assert_eq!(browser.enter().unwrap(), Some(directory.canonicalize().unwrap().join("a.txt")));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
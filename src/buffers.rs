@@ -0,0 +1,226 @@
+use macroquad::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::editor::Editor;
+use crate::files::{FileError, TextFile};
+use crate::palette::Palette;
+
+// Buffer list.
+// Holds every open editor, each one with its
+// own file, cursor and unsaved changes.
+// There is always at least one buffer open,
+// a scratch buffer when nothing else is
+pub struct Buffers {
+    pub editors: Vec<Editor>,   // Open buffers, in the order they were opened
+    pub current: usize          // Index of the buffer being edited
+}
+
+impl Buffers {
+
+    // Buffer list constructor, starts with a scratch buffer
+    pub fn new() -> Self {
+        Self {
+            editors: vec![Editor::new()],
+            current: 0
+        }
+    }
+
+    // Buffer being edited
+    pub fn current(&self) -> &Editor {
+        &self.editors[self.current]
+    }
+
+    // Buffer being edited, mutably
+    pub fn current_mut(&mut self) -> &mut Editor {
+        &mut self.editors[self.current]
+    }
+
+    // Index of the buffer holding the file, if it is open
+    pub fn find(&self, path: &Path) -> Option<usize> {
+        let path = canonical(path);
+
+        self.editors.iter().position(|editor| {
+            editor.file.as_ref().is_some_and(|file| canonical(&file.path) == path)
+        })
+    }
+
+    // Switch to the file, opening it
+    // in a new buffer if it isn't open yet
+    pub fn open(&mut self, path: &Path) -> Result<(), FileError> {
+        if let Some(index) = self.find(path) {
+            self.current = index;
+            return Ok(());
+        }
+
+        self.add(Editor::open(path)?);
+
+        Ok(())
+    }
+
+    // Open a file that might not exist yet,
+    // it is then created on the first write
+    pub fn open_or_new(&mut self, path: &Path) -> Result<(), FileError> {
+        match self.open(path) {
+            Err(FileError::NotFound(path)) => {
+                let mut editor = Editor::new();
+                editor.file = Some(TextFile::new(&path));
+                self.add(editor);
+                Ok(())
+            }
+            result => result
+        }
+    }
+
+    // Add a buffer and switch to it.
+    // An untouched scratch buffer is replaced
+    pub fn add(&mut self, editor: Editor) {
+        if self.current().is_untouched_scratch() {
+            self.editors[self.current] = editor;
+            return;
+        }
+
+        self.editors.push(editor);
+        self.current = self.editors.len() - 1;
+    }
+
+    // Switch to the next buffer, wrapping around
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.editors.len();
+    }
+
+    // Switch to the previous buffer, wrapping around
+    pub fn previous(&mut self) {
+        self.current = (self.current + self.editors.len() - 1) % self.editors.len();
+    }
+
+    // Close the current buffer and
+    // return the editor that was closed
+    pub fn close(&mut self) -> Editor {
+        let closed = self.editors.remove(self.current);
+
+        if self.editors.is_empty() {
+            self.editors.push(Editor::new());
+        }
+
+        self.current = self.current.min(self.editors.len() - 1);
+
+        closed
+    }
+
+    // Render the buffer tabs in the top bar,
+    // the current buffer is drawn highlighted
+    pub fn render_tabs(&self, top_bar_margin: f32, palette: &Palette) {
+        let font_size = 25.0;
+        let padding = 6.0;
+        let mut x = 5.0;
+
+        for (i, editor) in self.editors.iter().enumerate() {
+            let label = if editor.dirty {
+                format!("{} *", editor.file_name())
+            } else {
+                editor.file_name()
+            };
+
+            let width = measure_text(&label, None, font_size as u16, 1.0).width;
+
+            let color = if i == self.current {
+                draw_rectangle(x - padding, 0.0, width + padding * 2.0, top_bar_margin, palette.text);
+                palette.background
+            } else {
+                palette.text
+            };
+
+            draw_text(&label, x, 20.0, font_size, color);
+
+            // Tab seperator
+            x += width + padding;
+            draw_line(x, 0.0, x, top_bar_margin, 1.0, palette.line);
+            x += padding * 2.0;
+        }
+    }
+
+}
+
+// Resolve a path so the same file opened through
+// different paths lands in the same buffer
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An editor for a file, without touching the disk
+    fn editor_for(path: &Path) -> Editor {
+        let mut editor = Editor::new();
+        editor.file = Some(TextFile::new(path));
+        editor
+    }
+
+    #[test]
+    fn untouched_scratch_is_replaced() {
+        let mut buffers = Buffers::new();
+        buffers.add(editor_for(Path::new("a.txt")));
+        assert_eq!(buffers.editors.len(), 1);
+        assert_eq!(buffers.current().file_name(), "a.txt");
+
+        // Not once something was typed in it
+        let mut buffers = Buffers::new();
+        buffers.current_mut().insert_char('x');
+        buffers.add(editor_for(Path::new("a.txt")));
+        assert_eq!(buffers.editors.len(), 2);
+        assert_eq!(buffers.current, 1);
+    }
+
+    #[test]
+    fn switching_wraps_around() {
+        let mut buffers = Buffers::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            buffers.add(editor_for(Path::new(name)));
+        }
+        assert_eq!(buffers.current, 2);
+
+        buffers.next();
+        assert_eq!(buffers.current, 0);
+        buffers.previous();
+        assert_eq!(buffers.current, 2);
+        buffers.previous();
+        assert_eq!(buffers.current().file_name(), "b.txt");
+    }
+
+    #[test]
+    fn closing_the_last_buffer_leaves_a_scratch_one() {
+        let mut buffers = Buffers::new();
+        buffers.add(editor_for(Path::new("a.txt")));
+        buffers.add(editor_for(Path::new("b.txt")));
+
+        assert_eq!(buffers.close().file_name(), "b.txt");
+        assert_eq!(buffers.current().file_name(), "a.txt");
+
+        assert_eq!(buffers.close().file_name(), "a.txt");
+        assert_eq!(buffers.editors.len(), 1);
+        assert!(buffers.current().is_untouched_scratch());
+    }
+
+    #[test]
+    fn files_are_found_by_canonical_path() {
+        let directory = std::env::temp_dir().join(format!("whiskey-buffers-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("sub")).unwrap();
+        std::fs::write(directory.join("a.txt"), "a").unwrap();
+
+        let mut buffers = Buffers::new();
+        buffers.open(&directory.join("a.txt")).unwrap();
+        buffers.add(editor_for(&directory.join("b.txt")));
+
+        assert_eq!(buffers.find(&directory.join("sub/../a.txt")), Some(0));
+        assert_eq!(buffers.find(&directory.join("./b.txt")), Some(1));
+        assert_eq!(buffers.find(&directory.join("c.txt")), None);
+
+        // Opening it again switches to it
+        buffers.open(&directory.join("sub/../a.txt")).unwrap();
+        assert_eq!((buffers.editors.len(), buffers.current), (2, 0));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
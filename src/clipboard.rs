@@ -0,0 +1,60 @@
+use macroquad::miniquad::window;
+
+// Clipboard.
+// Copies go to the OS clipboard and to an in-process one,
+// pastes read the OS clipboard and fall back to the in-process
+// one when the OS has nothing for us.
+// Without a window (tests) only the in-process one is used.
+//
+// Whole line copies (nothing selected) are remembered as such,
+// so pasting them puts the line above the cursor instead of
+// splitting the line the cursor is on
+pub struct Clipboard {
+    text: String,       // In-process copy of the last copied text
+    line_wise: bool,    // Was the last copy a whole line
+    system: bool        // Use the OS clipboard too
+}
+
+impl Clipboard {
+
+    // Clipboard backed by the OS clipboard
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            line_wise: false,
+            system: true
+        }
+    }
+
+    // Clipboard that never touches the OS
+    #[cfg(test)]
+    pub fn headless() -> Self {
+        Self {
+            system: false,
+            ..Self::new()
+        }
+    }
+
+    // Copy text, 'line_wise' for whole lines
+    pub fn set(&mut self, text: String, line_wise: bool) {
+        if self.system {
+            window::clipboard_set(&text);
+        }
+
+        self.text = text;
+        self.line_wise = line_wise;
+    }
+
+    // Text to paste and whether it is whole lines.
+    // Text copied outside the editor is never line wise
+    pub fn get(&self) -> (String, bool) {
+        if self.system
+            && let Some(text) = window::clipboard_get()
+            && text != self.text {
+            return (text, false);
+        }
+
+        (self.text.clone(), self.line_wise)
+    }
+
+}
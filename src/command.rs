@@ -1,63 +1,473 @@
-// Command indeces.
-// After reading the command string
-// we will return one of these enums
-// that will be used as indeces to 
-// an array of functions
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::buffers::Buffers;
+use crate::files::FileError;
+use crate::settings::Settings;
+use super::Console;
+
+// Exit code of '?e'
+const EXIT_CODE: i32 = 3;
+
+// Exit the process, kills the editor
+pub fn exit() -> ! {
+    println!("Whiskey: Force exit");
+    std::process::exit(EXIT_CODE);
+}
+
+// Command registry.
+// Every '?' command is described by a spec: its name, the arguments
+// and flags it takes and the function running it. Input is split into
+// tokens and checked against the spec before the handler sees it,
+// so handlers only deal with arguments of the right count and type.
 //
-// The special case where we use the 
-// 'fname' -w
-//  or
-// 'fname'
-// we will return the enumerator value and handle
-// it accordingly elsewhere
-pub enum COMMAND_INDECES {
-    CommandUnkown = -4, // Invalid command
-
-    CommandSwitchAndSaveCurrentFile = -3,
-    CommandSwitchCurrentFile = -2,
-    CommandFileHandle = -1,
-
-    CommandSwitchDirectory = 0,
-    CommandWriteCurrentFile,
-    CommandDeleteFile,
-    CommandExit,
-    CommandPalleteSwitch,
-    CommandGoToLine 
-}
-
-// Command name vector
-// we will check here when for a '?' character
-// character is found in an inputed line
-// (when in console mode)
-pub const COMMAND_VECTOR: [&str ; 6] = [
-    "cd",
-    "wf",
-    "rf",
-    "e",
-    "p",
-    "l"
-];
-
-// Command struct
-pub struct ConsoleCommand {
-    pub command_index: i8,      // Index assigned
-    pub text: String            // Command string, when split we will find the command and its parameters
-}
-
-impl ConsoleCommand {
-
-    // Command constructor
-    pub fn new() -> Self {
-        Self { 
-            command_index: 0,
-            text: String::new(),
+// Tokens are separated by whitespace, quotes ("..." or '...') and
+// backslashes ('\ ') keep spaces inside a token, an unquoted '-' followed
+// by letters is a flag ('-w'), anything else is an argument ('-5' too)
+
+// Runs a command, returns the message to show
+pub type Handler = fn(&mut Console, &mut Buffers, &mut Settings, &ParsedCommand) -> Result<String, CommandError>;
+
+// What an argument has to be
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgumentKind {
+    Text,       // Anything
+    Path,       // A file or directory
+    Number,     // A whole number, 0 or more
+    Palette     // The name of a palette
+}
+
+// A command the console knows
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,                             // Typed after '?', empty for the 'fname' command
+    pub arguments: &'static [(&'static str, ArgumentKind)], // Name shown in the usage and kind of each argument
+    pub optional: usize,                                // How many of the last arguments can be left out
+    pub flags: &'static [&'static str],                 // Flags it accepts, without the '-'
+    pub handler: Handler
+}
+
+impl CommandSpec {
+
+    // How the command is typed, like '?wf [fname]'
+    pub fn usage(&self) -> String {
+        let required = self.arguments.len() - self.optional;
+
+        let mut usage = if self.name.is_empty() { Vec::new() } else { vec![format!("?{}", self.name)] };
+        for (i, (name, _)) in self.arguments.iter().enumerate() {
+            usage.push(if i < required { name.to_string() } else { format!("[{}]", name) });
+        }
+        for flag in self.flags {
+            usage.push(format!("[-{}]", flag));
         }
+
+        usage.join(" ")
     }
 
-    // Exit the process, kills the editor
-    pub fn exit() {
-        print!("Whiskey: Force exit\n");
-        std::process::exit(COMMAND_INDECES::CommandExit as i32);
+}
+
+// A token of the command line
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Word(String),   // Command name or argument
+    Flag(String)    // '-w' and the like, without the '-'
+}
+
+// A command checked against its spec
+pub struct ParsedCommand {
+    pub spec: &'static CommandSpec,
+    pub arguments: Vec<String>,
+    pub flags: Vec<String>
+}
+
+impl ParsedCommand {
+
+    // Argument at an index, if it was given
+    pub fn argument(&self, index: usize) -> Option<&str> {
+        self.arguments.get(index).map(String::as_str)
     }
 
+    // Number argument at an index, if it was given
+    pub fn number(&self, index: usize) -> Option<u64> {
+        self.argument(index).and_then(|argument| argument.parse().ok())
+    }
+
+    // Was a flag given
+    pub fn flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|given| given == flag)
+    }
+
+}
+
+// Why a command couldn't run
+#[derive(Debug)]
+pub enum CommandError {
+    Unknown(String),                                    // No command with that name
+    UnclosedQuote,                                      // A quote was never closed
+    Arity(&'static CommandSpec),                        // Too few or too many arguments
+    Type { argument: &'static str, value: String, kind: ArgumentKind }, // Argument of the wrong kind
+    Flag(String, &'static CommandSpec),                 // Flag the command doesn't take
+    File(FileError),                                    // Reading or writing a file failed
+    Failed(String)                                      // The command itself refused or failed
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "unknown command '?{}'", name),
+            CommandError::UnclosedQuote => write!(f, "unclosed quote"),
+            CommandError::Arity(spec) => write!(f, "wrong number of arguments, usage: {}", spec.usage()),
+            CommandError::Type { argument, value, kind } => {
+                let expected = match kind {
+                    ArgumentKind::Number => "a number",
+                    ArgumentKind::Path => "a path",
+                    ArgumentKind::Text => "text",
+                    ArgumentKind::Palette => "a palette name"
+                };
+                write!(f, "{} must be {}, got '{}'", argument, expected, value)
+            }
+            CommandError::Flag(flag, spec) => write!(f, "unknown flag '-{}', usage: {}", flag, spec.usage()),
+            CommandError::File(error) => write!(f, "{}", error),
+            CommandError::Failed(reason) => write!(f, "{}", reason)
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<FileError> for CommandError {
+    fn from(error: FileError) -> Self {
+        CommandError::File(error)
+    }
+}
+
+// Split a command line into tokens
+pub fn tokenize(text: &str) -> Result<Vec<Token>, CommandError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(tokens);
+        };
+
+        let mut word = String::new();
+        let mut literal = false; // Quoted or escaped somewhere, can't be a flag
+
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '"' | '\'' => {
+                    literal = true;
+                    loop {
+                        match chars.next() {
+                            Some(close) if close == c => break,
+                            // Double quotes can hold escaped quotes
+                            Some('\\') if c == '"' && matches!(chars.peek(), Some('"' | '\\')) => {
+                                word.extend(chars.next());
+                            }
+                            Some(inside) => word.push(inside),
+                            None => return Err(CommandError::UnclosedQuote)
+                        }
+                    }
+                }
+                // Only spaces, quotes and backslashes are escaped,
+                // so Windows paths keep their backslashes
+                '\\' if matches!(chars.peek(), Some(next) if next.is_whitespace() || matches!(next, '"' | '\'' | '\\')) => {
+                    literal = true;
+                    word.extend(chars.next());
+                }
+                _ => word.push(c)
+            }
+        }
+
+        let flag = !literal
+            && first == '-'
+            && word.len() > 1
+            && word[1..].chars().all(|c| c.is_ascii_alphabetic());
+
+        tokens.push(if flag { Token::Flag(word[1..].to_string()) } else { Token::Word(word) });
+    }
+}
+
+// Parse a command line against the registry.
+// Lines starting with '?' run the named command, others the one
+// with an empty name. None for an empty line
+pub fn parse(text: &str, commands: &'static [CommandSpec]) -> Result<Option<ParsedCommand>, CommandError> {
+    let mut tokens = tokenize(text.trim())?.into_iter().peekable();
+    if tokens.peek().is_none() {
+        return Ok(None);
+    }
+
+    let named = text.trim_start().starts_with('?');
+    let name = match tokens.next_if(|_| named) {
+        // Only the one '?', '??wf' isn't '?wf'
+        Some(Token::Word(word)) => word.strip_prefix('?').unwrap_or(&word).to_string(),
+        Some(Token::Flag(flag)) => format!("-{}", flag),
+        None => String::new()
+    };
+
+    // A '?' on its own names no command, not the unnamed one
+    if named && name.is_empty() {
+        return Err(CommandError::Unknown(name));
+    }
+
+    let spec = commands.iter()
+        .find(|spec| spec.name == name)
+        .ok_or(CommandError::Unknown(name))?;
+
+    let mut arguments = Vec::new();
+    let mut flags = Vec::new();
+    for token in tokens {
+        match token {
+            Token::Word(word) => arguments.push(word),
+            Token::Flag(flag) if spec.flags.contains(&flag.as_str()) => flags.push(flag),
+            Token::Flag(flag) => return Err(CommandError::Flag(flag, spec))
+        }
+    }
+
+    let required = spec.arguments.len() - spec.optional;
+    if !(required..=spec.arguments.len()).contains(&arguments.len()) {
+        return Err(CommandError::Arity(spec));
+    }
+
+    for (value, (argument, kind)) in arguments.iter().zip(spec.arguments) {
+        let valid = match kind {
+            ArgumentKind::Number => value.parse::<u64>().is_ok(),
+            ArgumentKind::Path | ArgumentKind::Palette => !value.is_empty(),
+            ArgumentKind::Text => true
+        };
+
+        if !valid {
+            return Err(CommandError::Type { argument, value: value.clone(), kind: *kind });
+        }
+    }
+
+    Ok(Some(ParsedCommand { spec, arguments, flags }))
+}
+
+// Completions for the word before the cursor, at the end of 'text':
+// command names after a '?', file and directory names for path arguments,
+// the names in 'palettes' for palette arguments.
+// Returns where the word starts and the candidates to replace it with
+pub fn complete(text: &str, commands: &[CommandSpec], palettes: &[String]) -> Option<(usize, Vec<String>)> {
+    // The word starts after the last unescaped space
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c.is_whitespace() {
+            start = i + c.len_utf8();
+        }
+    }
+
+    let typed = match tokenize(&text[start..]).ok()?.pop() {
+        Some(Token::Word(word)) => word,
+        Some(Token::Flag(_)) => return None,
+        None => String::new()
+    };
+    let before = tokenize(&text[..start]).ok()?;
+    let named = text.trim_start().starts_with('?');
+
+    // Command names
+    if named && before.is_empty() {
+        let name = typed.strip_prefix('?').unwrap_or(&typed);
+        let candidates: Vec<String> = commands.iter()
+            .filter(|spec| !spec.name.is_empty() && spec.name.starts_with(name))
+            .map(|spec| format!("?{}", spec.name))
+            .collect();
+
+        return Some((start, candidates));
+    }
+
+    // Arguments, by the kind the command expects at that position
+    let name = match before.first() {
+        Some(Token::Word(word)) if named => word.strip_prefix('?').unwrap_or(word),
+        _ => ""
+    };
+    if named && name.is_empty() {
+        return None;
+    }
+    let spec = commands.iter().find(|spec| spec.name == name)?;
+
+    let words = before.iter().filter(|token| matches!(token, Token::Word(_))).count();
+    let index = if named { words - 1 } else { words };
+
+    let candidates = match spec.arguments.get(index)?.1 {
+        ArgumentKind::Path => path_candidates(&typed),
+        ArgumentKind::Palette => palettes.iter().filter(|name| name.starts_with(typed.as_str())).cloned().collect(),
+        ArgumentKind::Text | ArgumentKind::Number => Vec::new()
+    };
+
+    // Spaces in names have to be escaped to stay a single argument
+    Some((start, candidates.iter().map(|candidate| candidate.replace(' ', "\\ ")).collect()))
+}
+
+// Files and directories starting with what was typed,
+// directories end with a '/' so completion can go on inside them.
+// Hidden ones only show up when asked for with a '.'
+fn path_candidates(typed: &str) -> Vec<String> {
+    let (directory, prefix) = match typed.rfind(['/', '\\']) {
+        Some(slash) => (&typed[..=slash], &typed[slash + 1..]),
+        None => ("", typed)
+    };
+
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { Path::new(".") } else { Path::new(directory) }) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, slash))
+        })
+        .collect();
+
+    candidates.sort();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nothing(_: &mut Console, _: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        Ok(String::new())
+    }
+
+    static COMMANDS: [CommandSpec; 5] = [
+        CommandSpec { name: "", arguments: &[("fname", ArgumentKind::Path)], optional: 0, flags: &["w"], handler: nothing },
+        CommandSpec { name: "e", arguments: &[], optional: 0, flags: &[], handler: nothing },
+        CommandSpec { name: "l", arguments: &[("lnum", ArgumentKind::Text)], optional: 0, flags: &[], handler: nothing },
+        CommandSpec { name: "ut", arguments: &[("mins", ArgumentKind::Number)], optional: 0, flags: &[], handler: nothing },
+        CommandSpec { name: "p", arguments: &[("pname", ArgumentKind::Palette)], optional: 1, flags: &[], handler: nothing }
+    ];
+
+    fn words(tokens: &[&str]) -> Vec<Token> {
+        tokens.iter().map(|token| Token::Word(token.to_string())).collect()
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(tokenize("  ?wf  notes.txt ").unwrap(), words(&["?wf", "notes.txt"]));
+    }
+
+    #[test]
+    fn quotes_and_escapes_keep_spaces() {
+        assert_eq!(tokenize(r#"?wf "my notes.txt""#).unwrap(), words(&["?wf", "my notes.txt"]));
+        assert_eq!(tokenize("?wf 'my notes.txt'").unwrap(), words(&["?wf", "my notes.txt"]));
+        assert_eq!(tokenize(r"my\ notes.txt").unwrap(), words(&["my notes.txt"]));
+        assert_eq!(tokenize(r#""say \"hi\"""#).unwrap(), words(&[r#"say "hi""#]));
+        assert_eq!(tokenize(r"C:\Users\me").unwrap(), words(&[r"C:\Users\me"]));
+        assert!(matches!(tokenize("?wf \"notes"), Err(CommandError::UnclosedQuote)));
+    }
+
+    #[test]
+    fn flags_are_dashes_followed_by_letters() {
+        assert_eq!(tokenize("notes.txt -w -5 '-f'").unwrap(), vec![
+            Token::Word(String::from("notes.txt")),
+            Token::Flag(String::from("w")),
+            Token::Word(String::from("-5")),
+            Token::Word(String::from("-f"))
+        ]);
+    }
+
+    #[test]
+    fn names_match_exactly() {
+        assert!(matches!(parse("?exit", &COMMANDS), Err(CommandError::Unknown(name)) if name == "exit"));
+        assert!(matches!(parse("?list 5", &COMMANDS), Err(CommandError::Unknown(_))));
+        assert_eq!(parse("?e", &COMMANDS).unwrap().unwrap().spec.name, "e");
+        assert_eq!(parse("?l -5", &COMMANDS).unwrap().unwrap().argument(0), Some("-5"));
+
+        // One '?' and the name right after it
+        assert!(matches!(parse("? e", &COMMANDS), Err(CommandError::Unknown(name)) if name.is_empty()));
+        assert!(matches!(parse("?", &COMMANDS), Err(CommandError::Unknown(name)) if name.is_empty()));
+        assert!(matches!(parse("??e", &COMMANDS), Err(CommandError::Unknown(name)) if name == "?e"));
+    }
+
+    #[test]
+    fn checks_arity_types_and_flags() {
+        assert!(matches!(parse("?e now", &COMMANDS), Err(CommandError::Arity(_))));
+        assert!(matches!(parse("?ut", &COMMANDS), Err(CommandError::Arity(_))));
+        assert!(matches!(parse("?ut ten", &COMMANDS), Err(CommandError::Type { .. })));
+        assert!(matches!(parse("?ut 10 -f", &COMMANDS), Err(CommandError::Flag(..))));
+        assert_eq!(parse("?ut 10", &COMMANDS).unwrap().unwrap().number(0), Some(10));
+    }
+
+    #[test]
+    fn file_names_without_question_mark() {
+        let command = parse("my\\ notes.txt -w", &COMMANDS).unwrap().unwrap();
+        assert_eq!(command.spec.name, "");
+        assert_eq!(command.argument(0), Some("my notes.txt"));
+        assert!(command.flag("w"));
+
+        assert!(parse("   ", &COMMANDS).unwrap().is_none());
+    }
+
+    #[test]
+    fn completes_command_names() {
+        assert_eq!(complete("?u", &COMMANDS, &[]), Some((0, vec![String::from("?ut")])));
+        assert_eq!(complete("?", &COMMANDS, &[]).unwrap().1.len(), 4);
+        assert_eq!(complete("?x", &COMMANDS, &[]), Some((0, Vec::new())));
+        assert_eq!(complete("??u", &COMMANDS, &[]), Some((0, Vec::new())));
+
+        // Not the unnamed command's paths either
+        assert_eq!(complete("? ", &COMMANDS, &[]), None);
+        assert_eq!(complete("? ut ", &COMMANDS, &[]), None);
+    }
+
+    #[test]
+    fn completes_paths() {
+        let directory = std::env::temp_dir().join(format!("whiskey-complete-{}", std::process::id()));
+        fs::create_dir_all(directory.join("src")).unwrap();
+        fs::write(directory.join("my notes.txt"), "").unwrap();
+        fs::write(directory.join("main.rs"), "").unwrap();
+        fs::write(directory.join(".hidden"), "").unwrap();
+
+        let typed = format!("{}/m", directory.display());
+        let (start, candidates) = complete(&typed, &COMMANDS, &[]).unwrap();
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec![
+            format!("{}/main.rs", directory.display()),
+            format!("{}/my\\ notes.txt", directory.display())
+        ]);
+
+        let typed = format!("{}/", directory.display());
+        let (_, candidates) = complete(&typed, &COMMANDS, &[]).unwrap();
+        assert_eq!(candidates.len(), 3);
+        assert!(candidates.contains(&format!("{}/src/", directory.display())));
+
+        // Numbers and text have nothing to complete
+        assert_eq!(complete("?ut 1", &COMMANDS, &[]), Some((4, Vec::new())));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn completes_palette_names() {
+        let palettes = [String::from("dark"), String::from("gruvbox"), String::from("Ocean Dark")];
+
+        assert_eq!(complete("?p ", &COMMANDS, &palettes).unwrap().1, vec!["dark", "gruvbox", "Ocean\\ Dark"]);
+        assert_eq!(complete("?p g", &COMMANDS, &palettes), Some((3, vec![String::from("gruvbox")])));
+        assert_eq!(complete("?p x", &COMMANDS, &palettes), Some((3, Vec::new())));
+
+        // Only where a palette name goes
+        assert_eq!(complete("?ut ", &COMMANDS, &palettes), Some((4, Vec::new())));
+    }
+
+    #[test]
+    fn usage_lists_arguments_and_flags() {
+        assert_eq!(COMMANDS[0].usage(), "fname [-w]");
+        assert_eq!(COMMANDS[3].usage(), "?ut mins");
+    }
 }
@@ -1,9 +1,21 @@
 use macroquad::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::browser::Browser;
+use crate::buffers::Buffers;
+use crate::editor::{self, Editor};
+use crate::files::FileError;
+use crate::history;
+use crate::palette::Palette;
+use crate::recall::Recall;
+use crate::settings::Settings;
+use crate::themes::Themes;
+use crate::trash::{Trash, Trashed};
 
 #[path = "command.rs"]
 mod command;
-use command::ConsoleCommand;
-use crate::console::command::{COMMAND_VECTOR, COMMAND_INDECES};
+use command::{ArgumentKind, CommandError, CommandSpec, ParsedCommand};
 
 // Console struct.
 // Handles general directives like:
@@ -15,28 +27,127 @@ use crate::console::command::{COMMAND_VECTOR, COMMAND_INDECES};
 // 'fname': Typing the name of a file, switches over to it without saving
 // if found, else it asks to create it
 //
-// '?cd': Switch cwd -> call upon windows to open the folder panel
+// '?cd': Browse the directories, picking the working directory or a file to open
+//
+// '?cd path': Change the working directory to 'path'
 //
 // '?wf': Save the currently open file
 //
-// '?rf fname': Delete a file with name 'fname'
+// '?wf fname': Save the currently open file as 'fname'
+//
+// '?rf fname': Move the file 'fname' to the trash, after asking.
+// Refused when it is open with unsaved changes, unless '?rf fname -f'
+//
+// '?restore': Bring back the last file moved to the trash
 //
 // '?e': Exit the editor, terminate the program
 //
-// '?p pname': Pallete switch to a pallete with name 'pname'
+// '?p pname': Switch to the palette named 'pname', '?p' lists them.
+// Palettes are also loaded from theme files, see themes.rs
+//
+// '?l lnum': Go to line lnum in the current file, '?l lnum:col' to a column on it,
+// '?l +n' / '?l -n' n lines down/up from the cursor, '?l $' to the last line
+//
+// '?bl': List the open buffers
+//
+// '?bn' / '?bp': Switch to the next/previous buffer
+//
+// '?bc': Close the current buffer, '?bc -f' closes it even with unsaved changes
+//
+// '?ub': Switch the undo branch redo follows
 //
-// '?l lnum': Go to line lnum in the current file
+// '?ut mins': Bring the current file back to how it was 'mins' minutes ago
+//
+// '?wrap': Turn soft wrapping of long lines on/off
+//
+// The command line is edited like the text: arrows, Home/End, 'CTRL' for words,
+// 'SHIFT' to select, Delete, 'CTRL + K', undo, copy, cut and paste
+//
+// Up/Down bring back the commands run before, 'CTRL + R' searches them
+//
+// 'Tab' completes command names, paths and palette names, again to go through the candidates
+//
+// Arguments with spaces can be quoted ("my notes.txt") or escaped (my\ notes.txt)
 pub struct Console {
     pub console_mode: bool,             // Switch in and out of the console
-    pub command: command::ConsoleCommand,        // Command object
-    pub cursor_x: usize                 // Cursor position inside the command
+    pub input: Editor,                  // Command line, a single line editor
+    pub log: Vec<Message>,              // Results of the commands and other messages, oldest first
+    pub log_scroll: usize,              // Messages scrolled back from the newest one
+    pub prompt: Option<Prompt>,         // Question waiting for a (y/n) answer
+    pub recall: Recall,                 // Commands run before
+    pub search: Option<Search>,         // Reverse search through the commands, while typing one
+    pub completion: Option<Completion>, // Candidates 'Tab' goes through
+    pub browser: Option<Browser>,       // Directory browser opened by '?cd', takes the keys while open
+    pub trashed: Vec<Trashed>,          // Files '?rf' moved to the trash, last one last
+    pub themes: Themes                  // User palettes, from theme files
+}
+
+// Tab completion state
+pub struct Completion {
+    start: usize,                       // Where the completed word starts in the command
+    candidates: Vec<String>,
+    index: usize                        // Candidate on the command line
+}
+
+// Reverse search state
+pub struct Search {
+    pub query: String,                  // Text the command has to contain
+    pub found: Option<usize>            // Index of the matching command in the history
+}
+
+// How serious a message is, sets its colour
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error
+}
+
+// A line of the console log
+pub struct Message {
+    pub text: String,
+    pub severity: Severity
 }
 
+// Questions the console can ask.
+// The next command entered is taken as the answer
+pub enum Prompt {
+    CreateFile(PathBuf),                // File to switch to was not found
+    DeleteFile(PathBuf)                 // File '?rf' is about to move to the trash
+}
+
+// Commands the console knows, the one without
+// a name takes file names typed without a '?'
+static COMMANDS: [CommandSpec; 15] = [
+    CommandSpec { name: "", arguments: &[("fname", ArgumentKind::Path)], optional: 0, flags: &["w"], handler: Console::file_handle },
+    CommandSpec { name: "cd", arguments: &[("path", ArgumentKind::Path)], optional: 1, flags: &[], handler: Console::change_directory },
+    CommandSpec { name: "wf", arguments: &[("fname", ArgumentKind::Path)], optional: 1, flags: &[], handler: Console::write_file },
+    CommandSpec { name: "rf", arguments: &[("fname", ArgumentKind::Path)], optional: 0, flags: &["f"], handler: Console::delete_file },
+    CommandSpec { name: "restore", arguments: &[], optional: 0, flags: &[], handler: Console::restore_file },
+    CommandSpec { name: "e", arguments: &[], optional: 0, flags: &[], handler: Console::exit },
+    CommandSpec { name: "p", arguments: &[("pname", ArgumentKind::Palette)], optional: 1, flags: &[], handler: Console::palette_switch },
+    CommandSpec { name: "l", arguments: &[("lnum", ArgumentKind::Text)], optional: 0, flags: &[], handler: Console::goto_line },
+    CommandSpec { name: "bl", arguments: &[], optional: 0, flags: &[], handler: Console::buffer_list },
+    CommandSpec { name: "bn", arguments: &[], optional: 0, flags: &[], handler: Console::buffer_next },
+    CommandSpec { name: "bp", arguments: &[], optional: 0, flags: &[], handler: Console::buffer_previous },
+    CommandSpec { name: "bc", arguments: &[], optional: 0, flags: &["f"], handler: Console::buffer_close },
+    CommandSpec { name: "ub", arguments: &[], optional: 0, flags: &[], handler: Console::undo_branch },
+    CommandSpec { name: "ut", arguments: &[("mins", ArgumentKind::Number)], optional: 0, flags: &[], handler: Console::undo_time },
+    CommandSpec { name: "wrap", arguments: &[], optional: 0, flags: &[], handler: Console::wrap_toggle }
+];
+
 // Console height
 pub const CONSOLE_HEIGHT: f32 = 150.0;
 
 // Console font size
-const console_font_size: f32 = 30.0;
+const CONSOLE_FONT_SIZE: f32 = 30.0;
+
+// Log font size and line height
+const LOG_FONT_SIZE: f32 = 22.0;
+const LOG_LINE_HEIGHT: f32 = 24.0;
+
+// Most messages the log keeps
+const LOG_SIZE: usize = 500;
 
 impl Console {
 
@@ -44,11 +155,229 @@ impl Console {
     pub fn new() -> Self {
        Self{
             console_mode: false,
-            command: ConsoleCommand::new(),
-            cursor_x: 0
+            input: Self::command_line(),
+            log: Vec::new(),
+            log_scroll: 0,
+            prompt: None,
+            recall: Recall::new(),
+            search: None,
+            completion: None,
+            browser: None,
+            trashed: Vec::new(),
+            themes: Themes::new()
        }    
     }
 
+    // Add a message to the log,
+    // scrolling back down to it
+    pub fn push_message(&mut self, severity: Severity, text: impl Into<String>) {
+        self.log.push(Message { text: text.into(), severity });
+        if self.log.len() > LOG_SIZE {
+            self.log.remove(0);
+        }
+
+        self.log_scroll = 0;
+    }
+
+    // Messages that fit under the command line
+    fn log_rows() -> usize {
+        ((CONSOLE_HEIGHT - CONSOLE_FONT_SIZE - 10.0) / LOG_LINE_HEIGHT) as usize
+    }
+
+    // Scroll the log by some messages, back to older ones when positive
+    pub fn scroll_log(&mut self, delta: isize) {
+        let most = self.log.len().saturating_sub(Self::log_rows());
+        self.log_scroll = self.log_scroll.saturating_add_signed(delta).min(most);
+    }
+
+    // Scroll the log by a page
+    pub fn page_log(&mut self, back: bool) {
+        let rows = Self::log_rows() as isize;
+        self.scroll_log(if back { rows } else { -rows });
+    }
+
+    // Keep the command history in the user's config directory
+    pub fn persist_history(&mut self) {
+        if let Some(path) = Recall::default_path() {
+            self.recall = Recall::open(path);
+        }
+    }
+
+    // Load user palettes from the user's config directory
+    pub fn use_themes(&mut self, settings: &mut Settings) {
+        if let Some(path) = Themes::default_path() {
+            self.themes = Themes::open(path);
+            self.reload_themes(settings);
+        }
+    }
+
+    // Pick up changes to the theme files, reporting their mistakes.
+    // The palette in use follows the changes to its file
+    pub fn reload_themes(&mut self, settings: &mut Settings) {
+        let Some(errors) = self.themes.reload() else {
+            return;
+        };
+
+        for error in errors {
+            self.push_message(Severity::Error, format!("Error: {}", error));
+        }
+
+        if let Some(palette) = self.themes.find(&settings.palette.name)
+            && palette != settings.palette {
+            let message = format!("Reloaded palette {}", palette.name);
+            settings.palette = palette;
+            self.push_message(Severity::Info, message);
+        }
+    }
+
+    // Empty command line. Brackets and quotes
+    // are not closed for you, quotes matter to commands
+    fn command_line() -> Editor {
+        let mut input = Editor::new();
+        input.auto_pairs = false;
+        input
+    }
+
+    // The command line
+    pub fn text(&self) -> &str {
+        &self.input.text[0]
+    }
+
+    // Replace a byte range of the command line with some text,
+    // through the editor so it can be undone like typing
+    fn replace_input(&mut self, range: Range<usize>, text: &str) {
+        let line = &self.input.text[0];
+        self.input.anchor = Some((0, editor::column(line, range.start)));
+        self.input.cursor_x = editor::column(line, range.end);
+
+        if text.is_empty() {
+            self.input.delete_selection();
+        } else {
+            self.input.paste(text, false);
+        }
+    }
+
+    // Replace the command line, cursor at its end
+    fn set_command(&mut self, text: &str) {
+        self.replace_input(0..self.text().len(), text);
+        self.completion = None;
+    }
+
+    // Edit the command line like the text is edited.
+    // Editing while searching takes the command found
+    pub fn edit(&mut self, action: impl FnOnce(&mut Editor)) {
+        self.completion = None;
+        self.accept_search();
+        action(&mut self.input);
+    }
+
+    // Selected text, or the whole command line
+    pub fn copy(&self) -> String {
+        self.input.selected_text().unwrap_or_else(|| self.text().to_string())
+    }
+
+    // Copy and delete the selection, or the whole command line
+    pub fn cut(&mut self) -> String {
+        let text = self.copy();
+        if self.input.selection().is_some() {
+            self.edit(|input| { input.delete_selection(); });
+        } else {
+            self.set_command("");
+        }
+        text
+    }
+
+    // Paste at the cursor, line breaks become spaces
+    pub fn paste(&mut self, text: &str) {
+        let text = text.trim_end_matches(['\r', '\n']).replace("\r\n", " ").replace('\n', " ");
+        self.edit(|input| input.paste(&text, false));
+    }
+
+    // Complete the word before the cursor (Tab),
+    // or go to the next/previous candidate when already completing
+    pub fn complete(&mut self, back: bool) {
+        if self.search.is_some() {
+            return;
+        }
+
+        if let Some(completion) = &mut self.completion {
+            let count = completion.candidates.len();
+            completion.index = if back { (completion.index + count - 1) % count } else { (completion.index + 1) % count };
+        } else {
+            let palettes: Vec<String> = self.themes.all().into_iter().map(|palette| palette.name).collect();
+            let Some((start, candidates)) = command::complete(&self.text()[..self.input.cursor_byte()], &COMMANDS, &palettes) else {
+                return;
+            };
+            if candidates.is_empty() {
+                return;
+            }
+
+            self.completion = Some(Completion { start, candidates, index: 0 });
+        }
+
+        let Some(completion) = self.completion.take() else {
+            return;
+        };
+        let candidate = &completion.candidates[completion.index];
+        self.replace_input(completion.start..self.input.cursor_byte(), candidate);
+
+        // Nothing to go through with a single candidate
+        if completion.candidates.len() > 1 {
+            self.completion = Some(completion);
+        }
+    }
+
+    // Bring back the previous command (Up)
+    pub fn recall_previous(&mut self) {
+        if let Some(command) = self.recall.previous(&self.input.text[0]) {
+            let command = command.to_string();
+            self.set_command(&command);
+        }
+    }
+
+    // Bring back the next command (Down)
+    pub fn recall_next(&mut self) {
+        if let Some(command) = self.recall.next() {
+            let command = command.to_string();
+            self.set_command(&command);
+        }
+    }
+
+    // Start searching the history (CTRL + R),
+    // or go to the next older match when already searching
+    pub fn reverse_search(&mut self) {
+        match &mut self.search {
+            None => self.search = Some(Search { query: String::new(), found: None }),
+            Some(search) => {
+                let before = search.found.unwrap_or(self.recall.len());
+                if let Some(found) = self.recall.search(&search.query, before) {
+                    search.found = Some(found);
+                }
+            }
+        }
+    }
+
+    // Look for the query again after it changed
+    fn update_search(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.found = self.recall.search(&search.query, self.recall.len());
+        }
+    }
+
+    // Put the command found on the command line and stop searching
+    pub fn accept_search(&mut self) {
+        if let Some(Search { found: Some(found), .. }) = self.search.take()
+            && let Some(command) = self.recall.get(found) {
+            let command = command.to_string();
+            self.set_command(&command);
+        }
+    }
+
+    // Stop searching, leaving the command line as it was
+    pub fn cancel_search(&mut self) {
+        self.search = None;
+    }
+
     // Switch in and out of the console
     pub fn console_mode_switch(&mut self) {
         self.console_mode = !self.console_mode;
@@ -57,51 +386,366 @@ impl Console {
     // Take input in the console and
     // insert a character via keypress
     pub fn insert_char(&mut self, c: char) {
-        if self.cursor_x <= self.command.text.len() {
-            self.command.text.insert(self.cursor_x, c);
-            self.cursor_x += 1;
+        if let Some(search) = &mut self.search {
+            search.query.push(c);
+            self.update_search();
+            return;
         }
+
+        self.edit(|input| input.insert_char(c));
     }
 
-    // Backspace
-    pub fn backspace(&mut self) {
-        if self.cursor_x > 0 {
-            self.cursor_x -= 1;
-            self.command.text.remove(self.cursor_x);
+    // Backspace, a whole word with 'word'
+    pub fn backspace(&mut self, word: bool) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            self.update_search();
+            return;
         }
+
+        self.edit(|input| if word { input.delete_word_back() } else { input.backspace() });
+    }
+
+    // Delete, a whole word with 'word'
+    pub fn delete(&mut self, word: bool) {
+        self.edit(|input| if word { input.delete_word_forward() } else { input.delete() });
+    }
+
+    // Move the cursor along the command line,
+    // like in the text with 'CTRL' and 'SHIFT'
+    pub fn move_cursor(&mut self, key: KeyCode, ctrl: bool, shift: bool) {
+        self.edit(|input| {
+            if ctrl {
+                input.control_key_handle(key, shift);
+            } else if shift {
+                input.extend_selection(key);
+            } else {
+                input.move_cursor(key);
+            }
+        });
     }
 
     // Execute command, via the return/enter key press
-    pub fn execute(&mut self) {
-        let index = self.read_command();
+    pub fn execute(&mut self, buffers: &mut Buffers, settings: &mut Settings) {
+        if let Some(prompt) = self.prompt.take() {
+            self.answer_prompt(prompt, buffers);
+            self.clear();
+            return;
+        }
+
+        let text = self.text().to_string();
+        self.recall.push(&text);
+
+        let result = match command::parse(&text, &COMMANDS) {
+            Ok(Some(command)) => (command.spec.handler)(self, buffers, settings, &command),
+            Ok(None) => Ok(String::new()),
+            Err(error) => Err(error)
+        };
 
-        if index == COMMAND_INDECES::CommandExit as i32 {
-            ConsoleCommand::exit();
+        match result {
+            // Questions wait for an answer, make them stand out
+            Ok(message) if self.prompt.is_some() => self.push_message(Severity::Warning, message),
+            Ok(message) if !message.is_empty() => self.push_message(Severity::Info, message),
+            Ok(_) => {}
+            Err(error) => self.push_message(Severity::Error, format!("Error: {}", error))
         }
 
+        self.clear();
     }
 
-    pub fn read_command(&self) -> i32 {
-        // Handled elsewhere
-        if !self.command.text.starts_with('?') {
-            return COMMAND_INDECES::CommandFileHandle as i32;
+    // '?cd' and '?cd path'
+    fn change_directory(&mut self, _: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let Some(path) = command.argument(0) else {
+            let browser = Browser::open(Path::new("."))?;
+            let message = format!("Browsing {}", browser.directory.display());
+            self.browser = Some(browser);
+            self.console_mode = false;
+            return Ok(message);
+        };
+
+        // '~' for the home directory
+        let path = match (path.strip_prefix('~'), dirs::home_dir()) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => home.join(rest.trim_start_matches('/')),
+            _ => PathBuf::from(path)
+        };
+
+        std::env::set_current_dir(&path).map_err(|e| FileError::from_io(&path, e))?;
+        Ok(format!("Working directory is {}", working_directory()))
+    }
+
+    // Act on a key pressed while the directory browser is open
+    pub fn browse(&mut self, key: KeyCode, buffers: &mut Buffers) {
+        let Some(browser) = &mut self.browser else {
+            return;
+        };
+
+        let result = match key {
+            KeyCode::Up => {
+                browser.up();
+                Ok(())
+            }
+            KeyCode::Down => {
+                browser.down();
+                Ok(())
+            }
+            KeyCode::Backspace => browser.parent(),
+            KeyCode::Escape => {
+                self.browser = None;
+                Ok(())
+            }
+            KeyCode::Enter => match browser.enter() {
+                Ok(Some(file)) => {
+                    self.browser = None;
+                    buffers.open(&file).map(|()| {
+                        self.push_message(Severity::Info, format!("Switched to {}", buffers.current().file_name()));
+                    })
+                }
+                Ok(None) => Ok(()),
+                Err(error) => Err(error)
+            },
+            _ => Ok(())
+        };
+
+        if let Err(error) = result {
+            self.push_message(Severity::Error, format!("Error: {}", error));
+        }
+    }
+
+    // '?rf fname' and '?rf fname -f'.
+    // Only asks, the answer moves the file to the trash
+    fn delete_file(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let path = PathBuf::from(command.argument(0).unwrap_or_default());
+        let metadata = std::fs::metadata(&path).map_err(|e| FileError::from_io(&path, e))?;
+
+        if metadata.is_dir() {
+            return Err(CommandError::Failed(format!("{} is a directory", path.display())));
+        }
+
+        if let Some(index) = buffers.find(&path)
+            && buffers.editors[index].dirty
+            && !command.flag("f") {
+            return Err(CommandError::Failed(format!(
+                "{} has unsaved changes, use '?rf {} -f' to delete it anyway",
+                buffers.editors[index].file_name(),
+                command.argument(0).unwrap_or_default()
+            )));
+        }
+
+        let message = format!("Move {} to the trash? (y/n)", path.display());
+        self.prompt = Some(Prompt::DeleteFile(path));
+        Ok(message)
+    }
+
+    // '?restore'
+    fn restore_file(&mut self, _: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        let Some(trashed) = self.trashed.pop() else {
+            return Err(CommandError::Failed(String::from("Nothing to restore")));
+        };
+
+        match trashed.restore() {
+            Ok(()) => Ok(format!("Restored {}", trashed.original.display())),
+            Err(error) => {
+                self.trashed.push(trashed);
+                Err(error.into())
+            }
         }
+    }
 
-        // Trim '?' to match to to whatever
-        let cmd = self.command.text.trim_start_matches('?');
+    // '?p' and '?p pname'
+    fn palette_switch(&mut self, _: &mut Buffers, settings: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let Some(name) = command.argument(0) else {
+            return Ok(self.themes.all()
+                .iter()
+                .map(|palette| if palette.name == settings.palette.name { format!(">{}", palette.name) } else { palette.name.clone() })
+                .collect::<Vec<_>>()
+                .join("   "));
+        };
 
-        // Normal '?' command fallback
-        for (i, command) in COMMAND_VECTOR.iter().enumerate() {
-            if cmd.starts_with(command) {
-                return i as i32; // return the command index
+        match self.themes.find(name) {
+            Some(palette) => {
+                settings.palette = palette;
+                Ok(format!("Switched to palette {}", name))
             }
+            None => Err(CommandError::Failed(format!("No palette named {}, '?p' lists them", name)))
         }
+    }
 
-        COMMAND_INDECES::CommandUnkown as i32
+    // '?e'
+    fn exit(&mut self, _: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        command::exit();
+    }
+
+    // '?wf' and '?wf fname'
+    fn write_file(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let path = command.argument(0).map(Path::new);
+        let editor = buffers.current_mut();
+
+        let lines = editor.save(path)?;
+        Ok(format!("Wrote {} lines to {}", lines, editor.file_name()))
+    }
+
+    // '?l lnum'
+    fn goto_line(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let editor = buffers.current_mut();
+        let target = command.argument(0).unwrap_or_default();
+
+        let (line, column) = line_target(target, editor.cursor_y, editor.text.len()).map_err(CommandError::Failed)?;
+        editor.goto_line(line, column);
+
+        Ok(format!("Line {}, column {}", editor.cursor_y + 1, editor.cursor_x + 1))
+    }
+
+    // '?bl'
+    fn buffer_list(&mut self, buffers: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        Ok(buffers.editors
+            .iter()
+            .enumerate()
+            .map(|(i, editor)| {
+                let current = if i == buffers.current { ">" } else { "" };
+                let dirty = if editor.dirty { " *" } else { "" };
+                format!("{}{}: {}{}", current, i + 1, editor.file_name(), dirty)
+            })
+            .collect::<Vec<_>>()
+            .join("   "))
+    }
+
+    // '?bn'
+    fn buffer_next(&mut self, buffers: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        buffers.next();
+        Ok(format!("Switched to {}", buffers.current().file_name()))
+    }
+
+    // '?bp'
+    fn buffer_previous(&mut self, buffers: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        buffers.previous();
+        Ok(format!("Switched to {}", buffers.current().file_name()))
+    }
+
+    // '?bc' and '?bc -f'
+    fn buffer_close(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        if buffers.current().dirty && !command.flag("f") {
+            return Err(CommandError::Failed(format!(
+                "{} has unsaved changes, use '?bc -f' to close it anyway",
+                buffers.current().file_name()
+            )));
+        }
+
+        Ok(format!("Closed {}", buffers.close().file_name()))
+    }
+
+    // '?ub'
+    fn undo_branch(&mut self, buffers: &mut Buffers, _: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        Ok(match buffers.current_mut().next_branch() {
+            Some((branch, count)) => format!("Redo follows branch {}/{}", branch, count),
+            None => String::from("Nothing to redo here")
+        })
+    }
+
+    // '?ut mins'
+    fn undo_time(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let minutes = command.number(0).unwrap_or_default();
+        let time = history::now().saturating_sub(minutes.saturating_mul(60));
+        let steps = buffers.current_mut().goto_time(time);
+
+        Ok(format!("Went back to {} minutes ago ({} steps)", minutes, steps))
+    }
+
+    // '?wrap'
+    fn wrap_toggle(&mut self, _: &mut Buffers, settings: &mut Settings, _: &ParsedCommand) -> Result<String, CommandError> {
+        settings.soft_wrap = !settings.soft_wrap;
+        Ok(format!("Soft wrap {}", if settings.soft_wrap { "on" } else { "off" }))
+    }
+
+    // Clear the command line
+    fn clear(&mut self) {
+        self.input = Self::command_line();
+        self.completion = None;
+    }
+
+    // 'fname' and 'fname -w'.
+    // Switch over to a file's buffer, opening it if needed,
+    // writing the current one first with '-w'
+    fn file_handle(&mut self, buffers: &mut Buffers, _: &mut Settings, command: &ParsedCommand) -> Result<String, CommandError> {
+        let name = command.argument(0).unwrap_or_default();
+
+        if command.flag("w") {
+            buffers.current_mut().save(None)?;
+        }
+
+        let path = PathBuf::from(name);
+        match buffers.open(&path) {
+            Ok(()) => Ok(format!("Switched to {}", buffers.current().file_name())),
+            Err(FileError::NotFound(path)) => {
+                let message = format!("{} not found, create it? (y/n)", path.display());
+                self.prompt = Some(Prompt::CreateFile(path));
+                Ok(message)
+            }
+            Err(error) => Err(error.into())
+        }
+    }
+
+    // Act on the answer to a prompt
+    fn answer_prompt(&mut self, prompt: Prompt, buffers: &mut Buffers) {
+        let yes = self.text().trim().eq_ignore_ascii_case("y");
+
+        match prompt {
+            Prompt::CreateFile(path) => {
+                if !yes {
+                    self.push_message(Severity::Info, "Cancelled");
+                    return;
+                }
+
+                match Editor::create(&path) {
+                    Ok(created) => {
+                        buffers.add(created);
+                        let message = format!("Created {}", buffers.current().file_name());
+                        self.push_message(Severity::Info, message);
+                    }
+                    Err(error) => self.push_message(Severity::Error, format!("Error: {}", error))
+                }
+            }
+            Prompt::DeleteFile(path) => {
+                if !yes {
+                    self.push_message(Severity::Info, "Cancelled");
+                    return;
+                }
+
+                let Some(trash) = Trash::home() else {
+                    self.push_message(Severity::Error, "Error: no data directory to keep a trash in");
+                    return;
+                };
+
+                match trash.put(&path) {
+                    Ok(trashed) => {
+                        let message = format!("Moved {} to the trash, '?restore' brings it back", path.display());
+                        self.trashed.push(trashed);
+                        self.push_message(Severity::Info, message);
+                    }
+                    Err(error) => self.push_message(Severity::Error, format!("Error: {}", error))
+                }
+            }
+        }
+    }
+
+    // Top and height of the command line on screen
+    fn input_row() -> (f32, f32) {
+        (screen_height() - CONSOLE_HEIGHT + 5.0, CONSOLE_FONT_SIZE - 4.0)
+    }
+
+    // Where the cursor goes on the command line,
+    // after the query when searching
+    pub fn cursor_position(&self) -> (f32, f32, f32) {
+        let before = match &self.search {
+            Some(search) => format!("(reverse-search)'{}", search.query),
+            None => self.text()[..self.input.cursor_byte()].to_string()
+        };
+
+        let (top, height) = Self::input_row();
+        (5.0 + measure_text(&before, None, CONSOLE_FONT_SIZE as u16, 1.0).width, top, height)
     }
 
     // Render the console promt
-    pub fn render_console(&mut self) {
+    pub fn render_console(&mut self, palette: &Palette) {
 
         // Always at the bottom of the screen
         if self.console_mode {
@@ -110,7 +754,7 @@ impl Console {
                 screen_width() - 132.5,
                 20.0,
                 25.0,
-                WHITE
+                palette.text
             );
 
             // Display the bottom bar
@@ -118,14 +762,14 @@ impl Console {
             // Take text there
 
             // Console rectangle
-            draw_rectangle(0.0, screen_height() - CONSOLE_HEIGHT, screen_width(), CONSOLE_HEIGHT,BLACK);
+            draw_rectangle(0.0, screen_height() - CONSOLE_HEIGHT, screen_width(), CONSOLE_HEIGHT, palette.console_background);
 
             // Seperator from the file
             draw_line(0.0,
                 screen_height() - CONSOLE_HEIGHT,
                 screen_width(),
                 screen_height() - CONSOLE_HEIGHT,
-                2.5, WHITE);
+                2.5, palette.line);
 
                 // From main.rs
                 /*
@@ -143,21 +787,396 @@ impl Console {
                     );
                 */
 
-            draw_text(self.command.text.as_str(),
+            // The match and what is searched for, while searching
+            let line = match &self.search {
+                Some(search) => {
+                    let found = search.found.and_then(|found| self.recall.get(found)).unwrap_or_default();
+                    format!("(reverse-search)'{}': {}", search.query, found)
+                }
+                None => self.text().to_string()
+            };
+
+            // Selection behind the command
+            if self.search.is_none()
+                && let Some((start, end)) = self.input.selection() {
+                let x = |column: usize| 5.0 + measure_text(&line[..editor::byte_index(&line, column)], None, CONSOLE_FONT_SIZE as u16, 1.0).width;
+                let (top, height) = Self::input_row();
+                draw_rectangle(x(start.1), top, x(end.1) - x(start.1), height, palette.selection);
+            }
+
+            draw_text(line.as_str(),
                 5.0, 
                 screen_height() - 
                     CONSOLE_HEIGHT + 
-                    console_font_size 
-                , console_font_size, 
-                WHITE);
+                    CONSOLE_FONT_SIZE 
+                , CONSOLE_FONT_SIZE, 
+                palette.console_text);
+
+            // Completion candidates right under the prompt,
+            // the one on the command line stands out
+            let mut rows = Self::log_rows();
+            let mut first_row = 0;
+            if let Some(completion) = &self.completion {
+                let y = screen_height() - CONSOLE_HEIGHT + CONSOLE_FONT_SIZE + LOG_LINE_HEIGHT;
+                let mut x = 5.0;
+
+                for (i, candidate) in completion.candidates.iter().enumerate() {
+                    let color = if i == completion.index { palette.console_text } else { palette.dim };
+                    x += draw_text(candidate, x, y, LOG_FONT_SIZE, color).width + 15.0;
+                }
+
+                rows -= 1;
+                first_row = 1;
+            }
+
+            // Log under the prompt, newest message first
+            let newest = self.log.len() - self.log_scroll.min(self.log.len());
+            for (row, message) in self.log[..newest].iter().rev().take(rows).enumerate() {
+                let row = row + first_row;
+                let color = match message.severity {
+                    Severity::Info => palette.info,
+                    Severity::Warning => palette.warning,
+                    Severity::Error => palette.error
+                };
+
+                draw_text(message.text.as_str(),
+                    5.0,
+                    screen_height() -
+                        CONSOLE_HEIGHT +
+                        CONSOLE_FONT_SIZE +
+                        (row + 1) as f32 * LOG_LINE_HEIGHT
+                    , LOG_FONT_SIZE,
+                    color);
+            }
+
+            // Where in the log we are, when it doesn't fit
+            if self.log.len() > rows {
+                let position = format!("{}-{}/{}", newest.saturating_sub(rows) + 1, newest, self.log.len());
+                let width = measure_text(&position, None, LOG_FONT_SIZE as u16, 1.0).width;
+
+                draw_text(&position,
+                    screen_width() - width - 5.0,
+                    screen_height() - CONSOLE_HEIGHT + CONSOLE_FONT_SIZE,
+                    LOG_FONT_SIZE,
+                    palette.dim);
+            }
         } else {
             draw_text("TEXT MODE",
                 screen_width() - 100.0,
                 20.0,
                 25.0,
-                WHITE
+                palette.text
             );
         }
     }
 
 }
+
+// The working directory, '~' standing for the home directory
+pub fn working_directory() -> String {
+    let Ok(directory) = std::env::current_dir() else {
+        return String::from("?");
+    };
+
+    match dirs::home_dir().and_then(|home| directory.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(inside) if inside.as_os_str().is_empty() => String::from("~"),
+        Some(inside) => format!("~/{}", inside.display()),
+        None => directory.display().to_string()
+    }
+}
+
+// Line and column (from 0) a '?l' argument points to:
+// 'line', 'line:column', '+n' / '-n' lines from the cursor line
+// or '$' for the last line. Lines counted from the cursor are
+// clamped to the text, others have to be in it
+fn line_target(argument: &str, cursor_y: usize, lines: usize) -> Result<(usize, usize), String> {
+    let (line, column) = match argument.split_once(':') {
+        Some((line, column)) => (line, Some(column)),
+        None => (argument, None)
+    };
+
+    // Columns are clamped to the line by the editor
+    let column = match column {
+        Some(column) => match column.parse::<usize>() {
+            Ok(column) if column > 0 => column - 1,
+            _ => return Err(format!("'{}' is not a column number", column))
+        },
+        None => 0
+    };
+
+    let offset = |offset: &str| offset.parse::<usize>().map_err(|_| format!("'{}' is not a line offset", argument));
+
+    let line = if line == "$" {
+        lines - 1
+    } else if let Some(down) = line.strip_prefix('+') {
+        cursor_y.saturating_add(offset(down)?).min(lines - 1)
+    } else if let Some(up) = line.strip_prefix('-') {
+        cursor_y.saturating_sub(offset(up)?)
+    } else {
+        match line.parse::<usize>() {
+            Ok(line) if (1..=lines).contains(&line) => line - 1,
+            Ok(line) => return Err(format!("line {} is out of range (1-{})", line, lines)),
+            Err(_) => return Err(format!("'{}' is not a line number", line))
+        }
+    };
+
+    Ok((line, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_targets() {
+        assert_eq!(line_target("120", 0, 200), Ok((119, 0)));
+        assert_eq!(line_target("120:15", 0, 200), Ok((119, 14)));
+        assert_eq!(line_target("$", 0, 200), Ok((199, 0)));
+        assert_eq!(line_target("+10", 50, 200), Ok((60, 0)));
+        assert_eq!(line_target("-5", 50, 200), Ok((45, 0)));
+    }
+
+    #[test]
+    fn relative_line_targets_are_clamped() {
+        assert_eq!(line_target("+10", 195, 200), Ok((199, 0)));
+        assert_eq!(line_target("-10", 5, 200), Ok((0, 0)));
+    }
+
+    #[test]
+    fn invalid_line_targets() {
+        assert!(line_target("0", 0, 200).is_err());
+        assert!(line_target("201", 0, 200).is_err());
+        assert!(line_target("abc", 0, 200).is_err());
+        assert!(line_target("12:0", 0, 200).is_err());
+        assert!(line_target("12:x", 0, 200).is_err());
+        assert!(line_target("+x", 0, 200).is_err());
+    }
+
+    #[test]
+    fn log_keeps_the_newest_messages() {
+        let mut console = Console::new();
+        for i in 0..LOG_SIZE + 10 {
+            console.push_message(Severity::Info, format!("message {}", i));
+        }
+
+        assert_eq!(console.log.len(), LOG_SIZE);
+        assert_eq!(console.log[0].text, "message 10");
+    }
+
+    #[test]
+    fn log_scroll_stays_in_the_log() {
+        let mut console = Console::new();
+        for i in 0..10 {
+            console.push_message(Severity::Info, format!("message {}", i));
+        }
+
+        console.scroll_log(100);
+        assert_eq!(console.log_scroll, 10 - Console::log_rows());
+        console.page_log(false);
+        console.scroll_log(-100);
+        assert_eq!(console.log_scroll, 0);
+
+        // New messages scroll back down
+        console.scroll_log(2);
+        console.push_message(Severity::Error, "Error: oops");
+        assert_eq!(console.log_scroll, 0);
+    }
+
+    #[test]
+    fn recall_and_search_commands() {
+        let mut console = Console::new();
+        for command in ["?l 10", "?bl", "?l 20"] {
+            console.recall.push(command);
+        }
+
+        console.insert_char('?');
+        console.recall_previous();
+        console.recall_previous();
+        assert_eq!(console.text(), "?bl");
+        console.recall_next();
+        console.recall_next();
+        assert_eq!((console.text(), console.input.cursor_x), ("?", 1));
+
+        console.reverse_search();
+        console.insert_char('l');
+        console.insert_char(' ');
+        console.reverse_search();
+        console.accept_search();
+        assert_eq!(console.text(), "?l 10");
+        assert!(console.search.is_none());
+    }
+
+    #[test]
+    fn tab_cycles_through_candidates() {
+        let mut console = Console::new();
+        console.insert_char('?');
+        console.insert_char('b');
+
+        console.complete(false);
+        assert_eq!(console.text(), "?bl");
+        console.complete(false);
+        assert_eq!(console.text(), "?bn");
+        console.complete(true);
+        console.complete(true);
+        assert_eq!((console.text(), console.input.cursor_x), ("?bc", 3));
+
+        // Typing picks the candidate
+        console.insert_char(' ');
+        assert!(console.completion.is_none());
+
+        console.set_command("?wr");
+        console.complete(false);
+        assert_eq!(console.text(), "?wrap");
+        assert!(console.completion.is_none());
+    }
+
+    #[test]
+    fn command_line_edits_like_the_text() {
+        let mut console = Console::new();
+        for c in "?wf \"my notes\".txt".chars() {
+            console.insert_char(c);
+        }
+        // Quotes are not doubled up
+        assert_eq!(console.text(), "?wf \"my notes\".txt");
+
+        console.backspace(true);
+        assert_eq!(console.text(), "?wf \"my notes\".");
+
+        console.move_cursor(KeyCode::Home, false, false);
+        console.move_cursor(KeyCode::Right, true, true);
+        assert_eq!(console.copy(), "?");
+        console.move_cursor(KeyCode::Right, true, true);
+        assert_eq!(console.copy(), "?wf");
+        console.paste("?l\n");
+        assert_eq!((console.text(), console.input.cursor_x), ("?l \"my notes\".", 2));
+
+        console.move_cursor(KeyCode::End, false, false);
+        console.move_cursor(KeyCode::Left, false, false);
+        console.delete(false);
+        assert_eq!(console.text(), "?l \"my notes\"");
+
+        console.edit(Editor::undo);
+        assert_eq!(console.text(), "?l \"my notes\".");
+        assert_eq!(console.cut(), "?l \"my notes\".");
+        assert_eq!(console.text(), "");
+    }
+
+    #[test]
+    fn delete_asks_and_refuses_unsaved_buffers() {
+        let path = std::env::temp_dir().join(format!("whiskey-rf-{}.txt", std::process::id()));
+        std::fs::write(&path, "text").unwrap();
+
+        let mut console = Console::new();
+        let mut buffers = Buffers::new();
+        let mut settings = Settings::new();
+        buffers.open(&path).unwrap();
+        buffers.current_mut().dirty = true;
+
+        let mut run = |console: &mut Console, buffers: &mut Buffers, command: &str| {
+            console.paste(command);
+            console.execute(buffers, &mut settings);
+        };
+
+        run(&mut console, &mut buffers, &format!("?rf {}", path.display()));
+        assert!(console.prompt.is_none());
+        assert_eq!(console.log.last().unwrap().severity, Severity::Error);
+
+        run(&mut console, &mut buffers, &format!("?rf {} -f", path.display()));
+        assert!(matches!(&console.prompt, Some(Prompt::DeleteFile(_))));
+
+        run(&mut console, &mut buffers, "n");
+        assert!(console.prompt.is_none());
+        assert!(path.exists());
+
+        run(&mut console, &mut buffers, "?restore");
+        assert_eq!(console.log.last().unwrap().text, "Error: Nothing to restore");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn palettes_switch_by_name() {
+        let mut console = Console::new();
+        let mut buffers = Buffers::new();
+        let mut settings = Settings::new();
+
+        console.paste("?p gruvbox");
+        console.execute(&mut buffers, &mut settings);
+        assert_eq!(settings.palette.name, "gruvbox");
+
+        console.paste("?p neon");
+        console.execute(&mut buffers, &mut settings);
+        assert_eq!(settings.palette.name, "gruvbox");
+        assert_eq!(console.log.last().unwrap().severity, Severity::Error);
+
+        console.paste("?p");
+        console.execute(&mut buffers, &mut settings);
+        assert_eq!(console.log.last().unwrap().text, "dark   light   solarized   >gruvbox");
+    }
+
+    #[test]
+    fn wrap_toggles_the_setting() {
+        let mut console = Console::new();
+        let mut buffers = Buffers::new();
+        let mut settings = Settings::new();
+
+        console.paste("?wrap");
+        console.execute(&mut buffers, &mut settings);
+        assert!(settings.soft_wrap);
+        assert_eq!(console.log.last().unwrap().text, "Soft wrap on");
+
+        console.paste("?wrap");
+        console.execute(&mut buffers, &mut settings);
+        assert!(!settings.soft_wrap);
+    }
+
+    #[test]
+    fn undo_time_takes_huge_minutes() {
+        let mut console = Console::new();
+        let mut buffers = Buffers::new();
+        let mut settings = Settings::new();
+        buffers.current_mut().insert_char('a');
+
+        console.paste("?ut 999999999999999999");
+        console.execute(&mut buffers, &mut settings);
+        assert_eq!(console.log.last().unwrap().severity, Severity::Info);
+        assert_eq!(buffers.current().text, vec![""]);
+    }
+
+    #[test]
+    fn missing_files_are_created_after_asking() {
+        let path = std::env::temp_dir().join(format!("whiskey-create-{}.txt", std::process::id()));
+        let mut console = Console::new();
+        let mut buffers = Buffers::new();
+        let mut settings = Settings::new();
+
+        let mut run = |console: &mut Console, buffers: &mut Buffers, command: &str| {
+            console.paste(command);
+            console.execute(buffers, &mut settings);
+        };
+
+        // No is the default for anything but 'y'
+        run(&mut console, &mut buffers, &path.display().to_string());
+        assert!(matches!(&console.prompt, Some(Prompt::CreateFile(_))));
+        assert_eq!(console.log.last().unwrap().severity, Severity::Warning);
+        run(&mut console, &mut buffers, "yes please");
+        assert!(console.prompt.is_none());
+        assert_eq!(console.log.last().unwrap().text, "Cancelled");
+        assert!(!path.exists());
+
+        run(&mut console, &mut buffers, &path.display().to_string());
+        run(&mut console, &mut buffers, " Y ");
+        assert!(path.exists());
+        assert_eq!(buffers.editors.len(), 1);
+        assert_eq!(buffers.current().file.as_ref().unwrap().path, path);
+
+        // Once it exists it is switched to straight away
+        buffers.add(Editor::new());
+        buffers.current_mut().insert_char('x');
+        run(&mut console, &mut buffers, &path.display().to_string());
+        assert!(console.prompt.is_none());
+        assert_eq!(buffers.current, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
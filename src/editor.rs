@@ -1,94 +1,457 @@
 use macroquad::{input::KeyCode};
+use std::path::Path;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::files::{self, FileError, TextFile};
+use crate::history::{self, Edit, EditKind, History, Position};
+use crate::layout::Wrap;
+use crate::rope::Rope;
+use crate::viewport::Viewport;
 
 // Basic editor struct.
 // Handles cursor positions
-// and text editing
+// and text editing.
+//
+// The cursor column counts grapheme clusters (what is seen
+// as a single character on screen), not bytes, so multibyte
+// and combined characters are stepped over as a whole
 pub struct Editor {
-    pub text: Vec<String>, // File text string
-    pub cursor_x: usize,   // Current cursor position x
-    pub cursor_y: usize    // Current cursor position y
+    pub text: Rope,                 // File text lines
+    pub cursor_x: usize,            // Current cursor position x, in graphemes
+    pub cursor_y: usize,            // Current cursor position y
+    pub anchor: Option<Position>,   // Where the selection started, the cursor is its other end
+    pub file: Option<TextFile>,     // File on disk, None for a scratch buffer
+    pub dirty: bool,                // Unsaved changes since the last write
+    pub history: History,           // Undo/redo history
+    pub viewport: Viewport,         // Part of the text on screen
+    pub wrap: Option<Wrap>,         // Soft wrapping, Up/Down move by row when set
+    pub auto_pairs: bool            // Close brackets and quotes when typing them
 }
 
 // Tab size
 const TAB_SIZE: usize = 4;
 
+// Pairs typed together, deleting the opening one
+// right before its closing one deletes both
+const AUTO_PAIRS: [(&str, &str); 5] = [("(", ")"), ("[", "]"), ("{", "}"), ("\"", "\""), ("'", "'")];
+
 impl Editor {
     
     // Editor constructor
     pub fn new() -> Self {
         Self {
-            text: vec![String::new()],
+            text: Rope::new(),
+            cursor_x: 0,
+            cursor_y: 0,
+            anchor: None,
+            file: None,
+            dirty: false,
+            history: History::new(),
+            viewport: Viewport::new(),
+            wrap: None,
+            auto_pairs: true
+        }
+    }
+
+    // Open a file from disk into a new editor,
+    // along with its undo history if it was saved
+    pub fn open(path: &Path) -> Result<Self, FileError> {
+        let (text, file) = files::load(path)?;
+
+        let history = files::read_undo(&file)
+            .and_then(|saved| History::deserialize(&saved, &text))
+            .unwrap_or_else(History::new);
+
+        Ok(Self {
+            text,
             cursor_x: 0,
-            cursor_y: 0
+            cursor_y: 0,
+            anchor: None,
+            file: Some(file),
+            dirty: false,
+            history,
+            viewport: Viewport::new(),
+            wrap: None,
+            auto_pairs: true
+        })
+    }
+
+    // Create a new empty file on disk and open it
+    pub fn create(path: &Path) -> Result<Self, FileError> {
+        let mut editor = Self::new();
+        editor.file = Some(files::create(path)?);
+
+        Ok(editor)
+    }
+
+    // Scratch buffer nothing was typed in yet
+    pub fn is_untouched_scratch(&self) -> bool {
+        self.file.is_none() && !self.dirty && self.text.len() == 1 && self.text[0].is_empty()
+    }
+
+    // File name for display purposes
+    pub fn file_name(&self) -> String {
+        match &self.file {
+            Some(file) => file.name(),
+            None => String::from("[scratch]")
+        }
+    }
+
+    // Write the text to disk.
+    // With a path given the file is saved under that
+    // path from now on (save as), keeping its format
+    pub fn save(&mut self, path: Option<&Path>) -> Result<usize, FileError> {
+        if let Some(path) = path {
+            match &mut self.file {
+                Some(file) => file.path = files::absolute(path),
+                None => self.file = Some(TextFile::new(path))
+            }
+        }
+
+        let file = self.file.as_ref().ok_or(FileError::NoPath)?;
+        let written = files::save(file, &self.text)?;
+        self.dirty = false;
+        self.history.mark_saved();
+
+        // Losing the history is not worth failing the write over
+        let _ = files::write_undo(file, &self.history.serialize(history::text_hash(&self.text)));
+
+        Ok(written)
+    }
+
+    // Byte index of the cursor in its line
+    pub fn cursor_byte(&self) -> usize {
+        byte_index(&self.text[self.cursor_y], self.cursor_x)
+    }
+
+    // Ordered (start, end) cursor positions of the selection,
+    // None when nothing is selected
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.anchor?;
+        let cursor = (self.cursor_y, self.cursor_x);
+
+        match anchor.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some((anchor, cursor)),
+            std::cmp::Ordering::Greater => Some((cursor, anchor)),
+            std::cmp::Ordering::Equal => None
+        }
+    }
+
+    // (line, byte) position of a (line, column) cursor position
+    fn byte_position(&self, (y, x): Position) -> Position {
+        (y, byte_index(&self.text[y], x))
+    }
+
+    // The selected text, lines joined with '\n'
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        Some(self.text_between(start, end))
+    }
+
+    // Text between two (line, column) positions, lines joined with '\n'
+    fn text_between(&self, start: Position, end: Position) -> String {
+        let (start, end) = (self.byte_position(start), self.byte_position(end));
+
+        if start.0 == end.0 {
+            return self.text[start.0][start.1..end.1].to_string();
+        }
+
+        let mut text = self.text[start.0][start.1..].to_string();
+        for y in start.0 + 1..end.0 {
+            text.push('\n');
+            text.push_str(&self.text[y]);
+        }
+        text.push('\n');
+        text.push_str(&self.text[end.0][..end.1]);
+
+        text
+    }
+
+    // Delete the text between two (line, column) positions,
+    // the cursor ends up at the start
+    fn delete_between(&mut self, start: Position, end: Position, kind: EditKind) {
+        let before = (self.cursor_y, self.cursor_x);
+        let text = self.text_between(start, end);
+        let at = self.byte_position(start);
+        self.delete_text(at, &text);
+
+        self.anchor = None;
+        (self.cursor_y, self.cursor_x) = start;
+        self.record(Edit::Delete { at, text }, kind, before);
+    }
+
+    // Delete the selected text, the cursor ends up
+    // where the selection started. Returns if anything was deleted
+    pub fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection() else {
+            self.anchor = None;
+            return false;
+        };
+
+        self.delete_between(start, end, EditKind::Other);
+
+        true
+    }
+
+    // Delete the selection something is about to be inserted over,
+    // the insertion is undone together with the deletion
+    fn replace_selection(&mut self) {
+        if self.delete_selection() {
+            self.history.join_next();
+        }
+    }
+
+    // Text to copy: the selection, or the whole
+    // current line when nothing is selected.
+    // Returns the text and whether it is a whole line
+    pub fn copy(&self) -> (String, bool) {
+        match self.selected_text() {
+            Some(text) => (text, false),
+            None => (format!("{}\n", self.text[self.cursor_y]), true)
+        }
+    }
+
+    // Cut the selection, or the whole current line
+    // when nothing is selected. Returns what copy would
+    pub fn cut(&mut self) -> (String, bool) {
+        let copied = self.copy();
+
+        if self.delete_selection() {
+            return copied;
         }
+
+        // Take the line along with a line break next to it
+        let before = (self.cursor_y, self.cursor_x);
+        let line = self.text[self.cursor_y].clone();
+        let (at, text) = if self.cursor_y + 1 < self.text.len() {
+            ((self.cursor_y, 0), format!("{}\n", line))
+        } else if self.cursor_y > 0 {
+            ((self.cursor_y - 1, self.text[self.cursor_y - 1].len()), format!("\n{}", line))
+        } else {
+            ((0, 0), line)
+        };
+
+        self.delete_text(at, &text);
+        self.cursor_y = at.0;
+        self.cursor_x = self.cursor_x.min(line_width(&self.text[self.cursor_y]));
+        self.record(Edit::Delete { at, text }, EditKind::Other, before);
+
+        copied
+    }
+
+    // Paste text as is, no auto indent or auto pairs,
+    // replacing the selection if there is one.
+    // Whole lines go above the current line
+    pub fn paste(&mut self, text: &str, line_wise: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        let text = text.replace("\r\n", "\n");
+
+        self.replace_selection();
+        let before = (self.cursor_y, self.cursor_x);
+
+        if line_wise {
+            let at = (self.cursor_y, 0);
+            let end = self.insert_text(at, &text);
+            self.cursor_y = end.0;
+            self.record(Edit::Insert { at, text }, EditKind::Other, before);
+            return;
+        }
+
+        let at = (self.cursor_y, self.cursor_byte());
+        let end = self.insert_text(at, &text);
+        (self.cursor_y, self.cursor_x) = (end.0, column(&self.text[end.0], end.1));
+        self.record(Edit::Insert { at, text }, EditKind::Other, before);
     }
 
-    // Insert a character via keypress
+    // Insert a character via keypress,
+    // replacing the selection if there is one
     pub fn insert_char(&mut self, c: char) {
+        self.replace_selection();
+        let before = (self.cursor_y, self.cursor_x);
+        let start = self.cursor_byte();
+
         if let Some(line) = self.text.get_mut(self.cursor_y) {
-            let special_char = Self::special_char_insertion(c, line, &mut self.cursor_x);
+            let len = line.len();
+            let mut index = start;
+            let special_char = self.auto_pairs && Self::special_char_insertion(c, line, &mut index);
             
             if !special_char {
-                line.insert(self.cursor_x, c);
-                self.cursor_x += 1;
+                line.insert(index, c);
+                index += c.len_utf8();
             }
+
+            // A combining character joins the previous grapheme,
+            // so the column is worked out from the byte index
+            self.cursor_x = column(line, index);
+
+            // Whatever got inserted, auto pairs included
+            let text = line[start..start + line.len() - len].to_string();
+            self.record(Edit::Insert { at: (self.cursor_y, start), text }, EditKind::Typing, before);
         }
     }
 
-    // Delete the previous character
+    // Delete the previous character,
+    // or the selection if there is one
     pub fn backspace(&mut self) {
-        if self.cursor_x > 0 {
-            let mut tab_check = false;
-
-            // Short immutable borrow
-            if self.cursor_x >= TAB_SIZE {
-                if let Some(line) = self.text.get(self.cursor_y) {
-                    let start = self.cursor_x - TAB_SIZE;
-                    let end = self.cursor_x;
-                    tab_check = line.get(start..end) == Some("    "); // 4 spaces
-                }
-            }
+        if self.delete_selection() {
+            return;
+        }
 
-            // Mutable borrow starts after immutable borrow ends
-            if let Some(line) = self.text.get_mut(self.cursor_y) {
-                if tab_check {
-                    let start = self.cursor_x - TAB_SIZE;
-                    let end = self.cursor_x;
-                    if start < end && end <= line.len() {
-                        line.drain(start..end); // safely remove that slice
-                        self.cursor_x -= TAB_SIZE;
-                    }
-                } else if self.cursor_x > 0 && self.cursor_x <= line.len() {
-                    line.remove(self.cursor_x - 1);
-                    self.cursor_x -= 1;
-                }
-            }
-        } else if self.cursor_y > 0 {
+        let (y, x) = (self.cursor_y, self.cursor_x);
+
+        if x > 0 {
+            // A "tab" of spaces goes all at once, an auto pair
+            // around the cursor both halves, anything else one grapheme at a time
+            let tab_check = x >= TAB_SIZE && self.text[y][..self.cursor_byte()].ends_with("    "); // 4 spaces
+            let (start, end) = if tab_check {
+                (x - TAB_SIZE, x)
+            } else if self.pair_at(x - 1) {
+                (x - 1, x + 1)
+            } else {
+                (x - 1, x)
+            };
+
+            self.delete_between((y, start), (y, end), EditKind::Deleting);
+        } else if y > 0 {
             // merge with previous line
-            let removed_line = self.text.remove(self.cursor_y);
-            self.cursor_y -= 1;
-            if let Some(prev_line) = self.text.get_mut(self.cursor_y) {
-                self.cursor_x = prev_line.len();
-                prev_line.push_str(&removed_line);
-            }
+            let end = line_width(&self.text[y - 1]);
+            self.delete_between((y - 1, end), (y, 0), EditKind::Deleting);
         }
     }
 
-    // Enter a new line
+    // Delete the next character, or the selection if there is one.
+    // At the end of a line the next line is joined to it
+    pub fn delete(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let (y, x) = (self.cursor_y, self.cursor_x);
+
+        if x < line_width(&self.text[y]) {
+            // An auto pair right after the cursor goes at once
+            let end = if self.pair_at(x) { x + 2 } else { x + 1 };
+            self.delete_between((y, x), (y, end), EditKind::Deleting);
+        } else if y + 1 < self.text.len() {
+            self.delete_between((y, x), (y + 1, 0), EditKind::Deleting);
+        }
+    }
+
+    // Delete back to the start of the word (Ctrl + Backspace),
+    // or the selection if there is one
+    pub fn delete_word_back(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let mut end = (self.cursor_y, self.cursor_x);
+        self.word_left();
+        let start = (self.cursor_y, self.cursor_x);
+        (self.cursor_y, self.cursor_x) = end;
+
+        // Like backspace, an auto pair around the cursor loses both halves
+        if start < end && end.1 > 0 && self.pair_at(end.1 - 1) {
+            end.1 += 1;
+        }
+
+        if start < end {
+            self.delete_between(start, end, EditKind::Other);
+        }
+    }
+
+    // Delete up to the end of the word (Ctrl + Delete),
+    // or the selection if there is one
+    pub fn delete_word_forward(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let mut start = (self.cursor_y, self.cursor_x);
+        self.word_right();
+        let end = (self.cursor_y, self.cursor_x);
+        (self.cursor_y, self.cursor_x) = start;
+
+        // Taking the closing half of an auto pair takes the opening one too
+        if start < end && start.1 > 0 && self.pair_at(start.1 - 1) {
+            start.1 -= 1;
+        }
+
+        if start < end {
+            self.delete_between(start, end, EditKind::Other);
+        }
+    }
+
+    // Delete the current line, line break included.
+    // The cursor keeps its column on the line taking its place
+    pub fn delete_line(&mut self) {
+        let (y, x) = (self.cursor_y, self.cursor_x);
+
+        let (start, end) = if y + 1 < self.text.len() {
+            ((y, 0), (y + 1, 0))
+        } else if y > 0 {
+            // The last line takes the break before it
+            ((y - 1, line_width(&self.text[y - 1])), (y, line_width(&self.text[y])))
+        } else {
+            ((0, 0), (0, line_width(&self.text[0])))
+        };
+
+        if start < end {
+            self.delete_between(start, end, EditKind::Other);
+        }
+        self.anchor = None;
+        self.cursor_x = x.min(line_width(&self.text[self.cursor_y]));
+    }
+
+    // Delete from the cursor to the end of the line,
+    // or the line break when already there
+    pub fn delete_to_line_end(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
+        let (y, x) = (self.cursor_y, self.cursor_x);
+        let width = line_width(&self.text[y]);
+
+        if x < width {
+            self.delete_between((y, x), (y, width), EditKind::Other);
+        } else if y + 1 < self.text.len() {
+            self.delete_between((y, x), (y + 1, 0), EditKind::Other);
+        }
+    }
+
+    // Are the graphemes at 'column' and the one after it
+    // an auto pair on the cursor's line, like '()'
+    fn pair_at(&self, column: usize) -> bool {
+        if !self.auto_pairs {
+            return false;
+        }
+
+        let mut graphemes = self.text[self.cursor_y].graphemes(true).skip(column);
+
+        match (graphemes.next(), graphemes.next()) {
+            (Some(open), Some(close)) => AUTO_PAIRS.contains(&(open, close)),
+            _ => false
+        }
+    }
+
+    // Enter a new line, replacing the selection if there is one
     pub fn new_line(&mut self) {
+        self.replace_selection();
+        let cursor = (self.cursor_y, self.cursor_x);
+        let start = (self.cursor_y, self.cursor_byte());
+
         if let Some(current_line) = self.text.get_mut(self.cursor_y) {
             // Split the current line at the cursor position
-            let rest = current_line.split_off(self.cursor_x);
+            let rest = current_line.split_off(start.1);
     
             // Count leading spaces on current line (indentation)
             let leading_spaces = current_line.chars().take_while(|c| *c == ' ').count();
     
             // Check characters around the cursor
-            let before = if self.cursor_x > 0 {
-                current_line.chars().nth(self.cursor_x - 1)
-            } else {
-                None
-            };
+            let before = current_line.chars().next_back();
             let after = rest.chars().next();
     
             // Are we between { and } ?
@@ -109,6 +472,9 @@ impl Editor {
     
                 // Place cursor at the new indented position
                 self.cursor_x = leading_spaces + TAB_SIZE;
+
+                let text = format!("\n{}\n{}", inner_indent, outer_indent);
+                self.record(Edit::Insert { at: start, text }, EditKind::Other, cursor);
             } else {
                 // Check if line ends with '{' → increase indent
                 let mut new_indent = leading_spaces;
@@ -119,13 +485,17 @@ impl Editor {
                 let indent = " ".repeat(new_indent);
                 self.text.insert(self.cursor_y, format!("{}{}", indent, rest));
                 self.cursor_x = new_indent;
+
+                let text = format!("\n{}", indent);
+                self.record(Edit::Insert { at: start, text }, EditKind::Other, cursor);
             }
         }
     }
 
     // Check for special character insertions
     // like when pressing '(' it creates another ')'
-    // or the tab -> <3 spaces>
+    // or the tab -> <3 spaces>.
+    // 'cursor_x' is a byte index into the line here
     pub fn special_char_insertion(c: char, line: &mut String, cursor_x: &mut usize) -> bool {
         if c == '(' {
             line.insert(*cursor_x, c);
@@ -175,55 +545,863 @@ impl Editor {
         false
     }
 
-    // Tab insertion
+    // Tab insertion, replacing the selection if there is one
     pub fn insert_tab(&mut self) {
+        self.replace_selection();
+        let before = (self.cursor_y, self.cursor_x);
+        let start = self.cursor_byte();
+
         if let Some(line) = self.text.get_mut(self.cursor_y) {
             for i in 0..TAB_SIZE {
-                line.insert(self.cursor_x + i, ' ');
+                line.insert(start + i, ' ');
             }
 
             self.cursor_x += TAB_SIZE;
+
+            let text = " ".repeat(TAB_SIZE);
+            self.record(Edit::Insert { at: (self.cursor_y, start), text }, EditKind::Typing, before);
         }
     }
 
-    // Move the cursor
-    pub fn move_cursor(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::Up => {
-                if self.cursor_y > 0 {
-                    self.cursor_y -= 1;
-                    self.cursor_x = self.cursor_x.min(self.text[self.cursor_y].len());
+    // Record an edit that was just applied, 'before' is the cursor
+    // before the edit, it is expected to be where the edit left it now
+    fn record(&mut self, edit: Edit, kind: EditKind, before: Position) {
+        self.dirty = true;
+        self.viewport.refollow();
+        self.history.record(edit, kind, before, (self.cursor_y, self.cursor_x));
+    }
+
+    // Undo the last step, the cursor goes back
+    // to where it was before the step
+    pub fn undo(&mut self) {
+        self.anchor = None;
+
+        if let Some(step) = self.history.undo() {
+            for edit in step.edits.iter().rev() {
+                match edit {
+                    Edit::Insert { at, text } => self.delete_text(*at, text),
+                    Edit::Delete { at, text } => { self.insert_text(*at, text); }
                 }
             }
-            KeyCode::Down => {
-                if self.cursor_y + 1 < self.text.len() {
-                    self.cursor_y += 1;
-                    self.cursor_x = self.cursor_x.min(self.text[self.cursor_y].len());
+
+            (self.cursor_y, self.cursor_x) = step.before;
+            self.dirty = !self.history.at_saved();
+            self.viewport.refollow();
+        }
+    }
+
+    // Redo the last undone step
+    pub fn redo(&mut self) {
+        self.anchor = None;
+
+        if let Some(step) = self.history.redo() {
+            for edit in step.edits.iter() {
+                match edit {
+                    Edit::Insert { at, text } => { self.insert_text(*at, text); }
+                    Edit::Delete { at, text } => self.delete_text(*at, text)
                 }
             }
+
+            (self.cursor_y, self.cursor_x) = step.after;
+            self.dirty = !self.history.at_saved();
+            self.viewport.refollow();
+        }
+    }
+
+    // Make redo follow the next branch of the history
+    pub fn next_branch(&mut self) -> Option<(usize, usize)> {
+        self.history.next_branch()
+    }
+
+    // Bring the text to the state it was in at the given time.
+    // Returns how many steps were undone and redone
+    pub fn goto_time(&mut self, time: u64) -> usize {
+        let target = self.history.state_at(time);
+        let (undos, redos) = self.history.path_to(target);
+
+        for _ in 0..undos {
+            self.undo();
+        }
+
+        for node in redos.iter() {
+            self.history.select(*node);
+            self.redo();
+        }
+
+        undos + redos.len()
+    }
+
+    // Insert text, possibly spanning multiple lines, at a (line, byte) position.
+    // Returns the position right after the inserted text
+    fn insert_text(&mut self, at: Position, text: &str) -> Position {
+        let (y, x) = at;
+        let rest = self.text[y].split_off(x);
+
+        let mut parts = text.split('\n');
+        self.text[y].push_str(parts.next().unwrap_or(""));
+
+        let mut end = (y, self.text[y].len());
+        for part in parts {
+            end = (end.0 + 1, part.len());
+            self.text.insert(end.0, part.to_string());
+        }
+
+        self.text[end.0].push_str(&rest);
+
+        end
+    }
+
+    // Delete text, possibly spanning multiple lines, at a (line, byte) position.
+    // The text is expected to be what is actually there
+    fn delete_text(&mut self, at: Position, text: &str) {
+        let (y, x) = at;
+        let lines = text.split('\n').count() - 1;
+
+        if lines == 0 {
+            self.text[y].drain(x..x + text.len());
+            return;
+        }
+
+        let last_len = text.len() - text.rfind('\n').unwrap_or(0) - 1;
+        let rest = self.text[y + lines].split_off(last_len);
+
+        self.text.drain(y + 1..=y + lines);
+        self.text[y].truncate(x);
+        self.text[y].push_str(&rest);
+    }
+
+    // Move the cursor, dropping the selection
+    pub fn move_cursor(&mut self, key: KeyCode) {
+        self.anchor = None;
+        self.step_cursor(key);
+    }
+
+    // Move the cursor, extending the selection
+    // (or starting one where the cursor was)
+    pub fn extend_selection(&mut self, key: KeyCode) {
+        self.anchor.get_or_insert((self.cursor_y, self.cursor_x));
+        self.step_cursor(key);
+    }
+
+    // Go to a line and column (from 0), clamped to the text,
+    // with the line in the middle of the screen
+    pub fn goto_line(&mut self, line: usize, column: usize) {
+        self.anchor = None;
+        self.cursor_y = line.min(self.text.len() - 1);
+        self.cursor_x = column.min(line_width(&self.text[self.cursor_y]));
+        self.viewport.center(self.cursor_y, self.text.len());
+    }
+
+    // Move the cursor one step in the key's direction
+    fn step_cursor(&mut self, key: KeyCode) {
+        if matches!(key, KeyCode::Up | KeyCode::Down) && self.step_row(key == KeyCode::Down) {
+            return;
+        }
+
+        match key {
+            KeyCode::Up if self.cursor_y > 0 => {
+                self.cursor_y -= 1;
+                self.cursor_x = self.cursor_x.min(line_width(&self.text[self.cursor_y]));
+            }
+            KeyCode::Down if self.cursor_y + 1 < self.text.len() => {
+                self.cursor_y += 1;
+                self.cursor_x = self.cursor_x.min(line_width(&self.text[self.cursor_y]));
+            }
             KeyCode::Left => {
                 if self.cursor_x > 0 {
                     self.cursor_x -= 1;
                 } else if self.cursor_y > 0 {
                     self.cursor_y -= 1;
-                    self.cursor_x = self.text[self.cursor_y].len();
+                    self.cursor_x = line_width(&self.text[self.cursor_y]);
                 }
             }
             KeyCode::Right => {
-                if self.cursor_x < self.text[self.cursor_y].len() {
+                if self.cursor_x < line_width(&self.text[self.cursor_y]) {
                     self.cursor_x += 1;
                 } else if self.cursor_y + 1 < self.text.len() {
                     self.cursor_y += 1;
                     self.cursor_x = 0;
                 }
             }
+            // Smart home, to the first non-blank of the line,
+            // or to the very start when already there
+            KeyCode::Home => {
+                let indent = self.text[self.cursor_y]
+                    .graphemes(true)
+                    .take_while(|grapheme| grapheme.chars().all(char::is_whitespace))
+                    .count();
+                self.cursor_x = if self.cursor_x == indent { 0 } else { indent };
+            }
+            KeyCode::End => {
+                self.cursor_x = line_width(&self.text[self.cursor_y]);
+            }
+            KeyCode::PageUp | KeyCode::PageDown => self.step_page(key == KeyCode::PageDown),
             _ => {}
         }
     }
 
-    // Control character handling
-    pub fn control_key_handle(&mut self, key: KeyCode){
-        
+    // Move the cursor and the view a screen up/down
+    fn step_page(&mut self, down: bool) {
+        let rows = self.viewport.rows.max(1);
+        let lines = self.text.len();
+        let delta = if down { rows as isize } else { -(rows as isize) };
+
+        self.cursor_y = self.cursor_y.saturating_add_signed(delta).min(lines - 1);
+        self.cursor_x = self.cursor_x.min(line_width(&self.text[self.cursor_y]));
+        self.viewport.scroll(delta, rows, lines);
+    }
+
+    // Move the cursor to the start of the previous word,
+    // over a run of graphemes of the same class
+    fn word_left(&mut self) {
+        if self.cursor_x == 0 {
+            self.step_cursor(KeyCode::Left);
+            return;
+        }
+
+        let line = &self.text[self.cursor_y];
+        let mut graphemes = line.graphemes(true).rev().skip(line_width(line) - self.cursor_x).peekable();
+
+        let mut column = self.cursor_x;
+        while graphemes.next_if(|grapheme| class(grapheme) == CharClass::Space).is_some() {
+            column -= 1;
+        }
+        if let Some(word) = graphemes.peek().map(|grapheme| class(grapheme)) {
+            while graphemes.next_if(|grapheme| class(grapheme) == word).is_some() {
+                column -= 1;
+            }
+        }
+
+        self.cursor_x = column;
+    }
+
+    // Move the cursor to the end of the next word
+    fn word_right(&mut self) {
+        let line = &self.text[self.cursor_y];
+        if self.cursor_x == line_width(line) {
+            self.step_cursor(KeyCode::Right);
+            return;
+        }
+
+        let mut graphemes = line.graphemes(true).skip(self.cursor_x).peekable();
+
+        let mut column = self.cursor_x;
+        while graphemes.next_if(|grapheme| class(grapheme) == CharClass::Space).is_some() {
+            column += 1;
+        }
+        if let Some(word) = graphemes.peek().map(|grapheme| class(grapheme)) {
+            while graphemes.next_if(|grapheme| class(grapheme) == word).is_some() {
+                column += 1;
+            }
+        }
+
+        self.cursor_x = column;
+    }
+
+    // Move the cursor up/down a row of soft wrapped text,
+    // keeping its offset from the start of the row.
+    // Returns false when not wrapping
+    fn step_row(&mut self, down: bool) -> bool {
+        let Some(wrap) = self.wrap.clone() else {
+            return false;
+        };
+
+        let rows = wrap.rows(&self.text[self.cursor_y]);
+        let row = Wrap::row_of(&rows, self.cursor_x);
+        let offset = self.cursor_x - rows[row].start;
+
+        let (y, rows, row) = if down {
+            if row + 1 < rows.len() {
+                (self.cursor_y, rows, row + 1)
+            } else if self.cursor_y + 1 < self.text.len() {
+                (self.cursor_y + 1, wrap.rows(&self.text[self.cursor_y + 1]), 0)
+            } else {
+                return true;
+            }
+        } else if row > 0 {
+            (self.cursor_y, rows, row - 1)
+        } else if self.cursor_y > 0 {
+            let rows = wrap.rows(&self.text[self.cursor_y - 1]);
+            let last = rows.len() - 1;
+            (self.cursor_y - 1, rows, last)
+        } else {
+            return true;
+        };
+
+        // The end of a row that isn't the last one
+        // is the start of the next, stop before it
+        let range = &rows[row];
+        let max = if row + 1 == rows.len() { range.end } else { (range.end - 1).max(range.start) };
+
+        self.cursor_y = y;
+        self.cursor_x = (range.start + offset).min(max);
+
+        true
+    }
+
+    // Control character handling.
+    // Ctrl + Left/Right jump over words, Ctrl + Home/End
+    // to the start/end of the text, other keys move as usual.
+    // 'select' extends the selection like Shift does
+    pub fn control_key_handle(&mut self, key: KeyCode, select: bool) {
+        if select {
+            self.anchor.get_or_insert((self.cursor_y, self.cursor_x));
+        } else {
+            self.anchor = None;
+        }
+
+        match key {
+            KeyCode::Left => self.word_left(),
+            KeyCode::Right => self.word_right(),
+            KeyCode::Home => {
+                self.cursor_y = 0;
+                self.cursor_x = 0;
+            }
+            KeyCode::End => {
+                self.cursor_y = self.text.len() - 1;
+                self.cursor_x = line_width(&self.text[self.cursor_y]);
+            }
+            _ => self.step_cursor(key)
+        }
+    }
+
+}
+
+// Kinds of characters word motions stop between
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    Space,
+    Word,           // Identifiers, letters, digits and '_'
+    Punctuation
+}
+
+// Class of a grapheme, by its first character
+fn class(grapheme: &str) -> CharClass {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Space,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Punctuation
+    }
+}
+
+// Number of graphemes in a line
+pub fn line_width(line: &str) -> usize {
+    line.graphemes(true).count()
+}
+
+// Byte index of a grapheme column in a line,
+// the end of the line past its last grapheme
+pub fn byte_index(line: &str, column: usize) -> usize {
+    line.grapheme_indices(true)
+        .nth(column)
+        .map_or(line.len(), |(index, _)| index)
+}
+
+// Grapheme column of a byte index in a line
+pub fn column(line: &str, index: usize) -> usize {
+    line.grapheme_indices(true)
+        .take_while(|(start, _)| *start < index)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::Clipboard;
+
+    fn type_str(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.insert_char(c);
+        }
+    }
+
+    #[test]
+    fn typing_multibyte_moves_by_grapheme() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "héllo");
+
+        assert_eq!(editor.text[0], "héllo");
+        assert_eq!(editor.cursor_x, 5);
+        assert_eq!(editor.cursor_byte(), "héllo".len());
+
+        editor.move_cursor(KeyCode::Left);
+        editor.move_cursor(KeyCode::Left);
+        editor.move_cursor(KeyCode::Left);
+        editor.move_cursor(KeyCode::Left);
+        assert_eq!(editor.cursor_x, 1);
+        assert_eq!(editor.cursor_byte(), 1);
+
+        editor.move_cursor(KeyCode::Right);
+        assert_eq!(editor.cursor_byte(), "hé".len());
+    }
+
+    #[test]
+    fn insert_between_multibyte_characters() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "日本");
+        editor.move_cursor(KeyCode::Left);
+        type_str(&mut editor, "x");
+
+        assert_eq!(editor.text[0], "日x本");
+        assert_eq!(editor.cursor_x, 2);
+    }
+
+    #[test]
+    fn emoji_is_a_single_column() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "a👍b");
+        editor.move_cursor(KeyCode::Left);
+        editor.move_cursor(KeyCode::Left);
+
+        assert_eq!(editor.cursor_x, 1);
+
+        editor.backspace();
+        assert_eq!(editor.text[0], "👍b");
+
+        editor.move_cursor(KeyCode::Right);
+        editor.backspace();
+        assert_eq!(editor.text[0], "b");
+        assert_eq!(editor.cursor_x, 0);
+    }
+
+    #[test]
+    fn combining_character_joins_previous_grapheme() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "e\u{301}");
+
+        assert_eq!(line_width(&editor.text[0]), 1);
+        assert_eq!(editor.cursor_x, 1);
+
+        editor.backspace();
+        assert!(editor.text[0].is_empty());
+        assert_eq!(editor.cursor_x, 0);
+    }
+
+    #[test]
+    fn family_emoji_moves_as_one() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let mut editor = Editor::new();
+        type_str(&mut editor, family);
+        type_str(&mut editor, "!");
+
+        assert_eq!(editor.cursor_x, 2);
+
+        editor.move_cursor(KeyCode::Left);
+        editor.move_cursor(KeyCode::Left);
+        assert_eq!(editor.cursor_x, 0);
+
+        editor.move_cursor(KeyCode::Right);
+        assert_eq!(editor.cursor_byte(), family.len());
+    }
+
+    #[test]
+    fn new_line_splits_after_multibyte() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ñandú");
+        editor.move_cursor(KeyCode::Left);
+        editor.new_line();
+
+        assert_eq!(editor.text, vec!["ñand", "ú"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 0));
+
+        editor.backspace();
+        assert_eq!(editor.text, vec!["ñandú"]);
+        assert_eq!(editor.cursor_x, 4);
+    }
+
+    #[test]
+    fn vertical_movement_clamps_to_graphemes() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ab");
+        editor.new_line();
+        type_str(&mut editor, "éééé");
+        editor.move_cursor(KeyCode::Up);
+
+        assert_eq!(editor.cursor_x, 2);
+        assert_eq!(editor.cursor_byte(), 2);
+
+        editor.move_cursor(KeyCode::Down);
+        assert_eq!(editor.cursor_byte(), "éé".len());
+    }
+
+    #[test]
+    fn auto_pairs_after_multibyte() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "λ(");
+
+        assert_eq!(editor.text[0], "λ()");
+        assert_eq!(editor.cursor_x, 2);
     }
 
+    #[test]
+    fn undo_redo_multibyte_edits() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ça");
+        editor.new_line();
+        type_str(&mut editor, "e\u{301}");
+        editor.backspace();
+
+        editor.undo();
+        assert_eq!(editor.text, vec!["ça", "e\u{301}"]);
+        assert_eq!(editor.cursor_x, 1);
+
+        while editor.history.current != 0 {
+            editor.undo();
+        }
+        assert_eq!(editor.text, vec![""]);
+
+        editor.redo();
+        editor.redo();
+        editor.redo();
+        assert_eq!(editor.text, vec!["ça", "e\u{301}"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 1));
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_text_is_clean() {
+        let path = std::env::temp_dir().join(format!("whiskey-editor-{}-saved.txt", std::process::id()));
+        let mut editor = Editor::new();
+        type_str(&mut editor, "one");
+        assert!(editor.dirty);
+
+        editor.undo();
+        assert!(!editor.dirty);
+        editor.redo();
+        assert!(editor.dirty);
+
+        editor.save(Some(&path)).unwrap();
+        assert!(!editor.dirty);
+
+        // Typing after a write starts a new step
+        type_str(&mut editor, "two");
+        editor.undo();
+        assert_eq!(editor.text, vec!["one"]);
+        assert!(!editor.dirty);
+
+        editor.undo();
+        assert!(editor.dirty);
+        editor.redo();
+        assert!(!editor.dirty);
+
+        let undo_file = files::undo_path(editor.file.as_ref().unwrap()).unwrap();
+        assert!(undo_file.exists());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(undo_file).unwrap();
+    }
+
+    #[test]
+    fn typing_replaces_selection() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "één");
+        editor.new_line();
+        type_str(&mut editor, "twö");
+        editor.move_cursor(KeyCode::Left);
+        editor.extend_selection(KeyCode::Up);
+        editor.extend_selection(KeyCode::Left);
+
+        assert_eq!(editor.selected_text().as_deref(), Some("én\ntw"));
+
+        type_str(&mut editor, "x");
+        assert_eq!(editor.text, vec!["éxö"]);
+        assert_eq!(editor.cursor_x, 2);
+        assert!(editor.selection().is_none());
+
+        // One undo brings the selected text back
+        editor.undo();
+        assert_eq!(editor.text, vec!["één", "twö"]);
+        editor.redo();
+        assert_eq!(editor.text, vec!["éxö"]);
+    }
+
+    #[test]
+    fn backspace_deletes_selection_only() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "abcd");
+        editor.extend_selection(KeyCode::Left);
+        editor.extend_selection(KeyCode::Left);
+        editor.backspace();
+
+        assert_eq!(editor.text, vec!["ab"]);
+        assert_eq!(editor.cursor_x, 2);
+
+        editor.move_cursor(KeyCode::Left);
+        assert!(editor.anchor.is_none());
+    }
+
+    #[test]
+    fn paste_multiple_lines_as_is() {
+        let mut clipboard = Clipboard::headless();
+        clipboard.set(String::from("{\r\n  (x\n\"é"), false);
+
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ab");
+        editor.move_cursor(KeyCode::Left);
+
+        let (text, line_wise) = clipboard.get();
+        editor.paste(&text, line_wise);
+
+        assert_eq!(editor.text, vec!["a{", "  (x", "\"éb"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (2, 2));
+
+        editor.undo();
+        assert_eq!(editor.text, vec!["ab"]);
+    }
+
+    #[test]
+    fn cut_and_paste_whole_line() {
+        let mut clipboard = Clipboard::headless();
+        let mut editor = Editor::new();
+        type_str(&mut editor, "one");
+        editor.new_line();
+        type_str(&mut editor, "two");
+        editor.move_cursor(KeyCode::Up);
+
+        let (text, line_wise) = editor.cut();
+        clipboard.set(text, line_wise);
+        assert_eq!(editor.text, vec!["two"]);
+
+        editor.move_cursor(KeyCode::Right);
+        let (text, line_wise) = clipboard.get();
+        editor.paste(&text, line_wise);
+        editor.paste(&text, line_wise);
+
+        assert_eq!(editor.text, vec!["one", "one", "two"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (2, 3));
+    }
+
+    #[test]
+    fn copy_selection() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "hello");
+        editor.extend_selection(KeyCode::Left);
+        editor.extend_selection(KeyCode::Left);
+
+        assert_eq!(editor.copy(), (String::from("lo"), false));
+        assert_eq!(editor.text, vec!["hello"]);
+    }
+
+    #[test]
+    fn up_down_move_by_wrapped_row() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "hello there world");
+        editor.new_line();
+        type_str(&mut editor, "ab");
+
+        // Every grapheme is 1 wide, rows are 0..6, 6..12, 12..17
+        editor.wrap = Some(Wrap {
+            width: 10.0,
+            measure: std::rc::Rc::new(|text: &str| text.chars().count() as f32)
+        });
+
+        editor.move_cursor(KeyCode::Up);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 14));
+
+        editor.move_cursor(KeyCode::Up);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 8));
+
+        // Never lands on the start of the next row
+        editor.cursor_x = 5;
+        editor.move_cursor(KeyCode::Down);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 11));
+
+        editor.move_cursor(KeyCode::Down);
+        editor.move_cursor(KeyCode::Down);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 2));
+    }
+
+    #[test]
+    fn ctrl_arrows_jump_over_words() {
+        let mut editor = Editor::new();
+        editor.text = Rope::from(vec![String::from("let x_1 = foo(); ")]);
+
+        let mut stops = Vec::new();
+        for _ in 0..6 {
+            editor.control_key_handle(KeyCode::Right, false);
+            stops.push(editor.cursor_x);
+        }
+        assert_eq!(stops, vec![3, 7, 9, 13, 16, 17]);
+
+        let mut stops = Vec::new();
+        for _ in 0..5 {
+            editor.control_key_handle(KeyCode::Left, false);
+            stops.push(editor.cursor_x);
+        }
+        assert_eq!(stops, vec![13, 10, 8, 4, 0]);
+    }
+
+    #[test]
+    fn ctrl_arrows_cross_lines_and_select() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "one");
+        editor.new_line();
+        type_str(&mut editor, "two");
+        editor.cursor_x = 0;
+
+        editor.control_key_handle(KeyCode::Left, true);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 3));
+        editor.control_key_handle(KeyCode::Left, true);
+        assert_eq!(editor.selected_text(), Some(String::from("one\n")));
+
+        editor.control_key_handle(KeyCode::End, false);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 3));
+        assert_eq!(editor.selection(), None);
+    }
+
+    #[test]
+    fn smart_home_and_end() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "    indented");
+
+        editor.move_cursor(KeyCode::Home);
+        assert_eq!(editor.cursor_x, 4);
+        editor.move_cursor(KeyCode::Home);
+        assert_eq!(editor.cursor_x, 0);
+        editor.move_cursor(KeyCode::End);
+        assert_eq!(editor.cursor_x, 12);
+    }
+
+    #[test]
+    fn page_down_moves_by_viewport_height() {
+        let mut editor = Editor::new();
+        for _ in 0..30 {
+            editor.new_line();
+        }
+        editor.control_key_handle(KeyCode::Home, false);
+        editor.viewport.rows = 10;
+
+        editor.move_cursor(KeyCode::PageDown);
+        assert_eq!((editor.cursor_y, editor.viewport.top), (10, 10));
+        editor.move_cursor(KeyCode::PageDown);
+        editor.move_cursor(KeyCode::PageDown);
+        editor.move_cursor(KeyCode::PageDown);
+        assert_eq!((editor.cursor_y, editor.viewport.top), (30, 21));
+        editor.move_cursor(KeyCode::PageUp);
+        assert_eq!((editor.cursor_y, editor.viewport.top), (20, 11));
+    }
+
+    #[test]
+    fn delete_forward_joins_lines() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ab");
+        editor.new_line();
+        type_str(&mut editor, "cd");
+        editor.control_key_handle(KeyCode::Home, false);
+
+        editor.delete();
+        assert_eq!(editor.text, vec!["b", "cd"]);
+        editor.move_cursor(KeyCode::End);
+        editor.delete();
+        assert_eq!(editor.text, vec!["bcd"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 1));
+
+        // At the very end nothing happens
+        editor.move_cursor(KeyCode::End);
+        editor.delete();
+        assert_eq!(editor.text, vec!["bcd"]);
+
+        editor.undo();
+        assert_eq!(editor.text, vec!["b", "cd"]);
+    }
+
+    #[test]
+    fn deleting_auto_pairs() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "f(");
+        assert_eq!(editor.text, vec!["f()"]);
+
+        editor.backspace();
+        assert_eq!(editor.text, vec!["f"]);
+
+        type_str(&mut editor, "[");
+        editor.move_cursor(KeyCode::Left);
+        editor.delete();
+        assert_eq!(editor.text, vec!["f"]);
+    }
+
+    #[test]
+    fn deleting_words_takes_auto_pairs() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "foo(");
+        assert_eq!(editor.text, vec!["foo()"]);
+
+        editor.delete_word_back();
+        assert_eq!(editor.text, vec!["foo"]);
+        assert_eq!(editor.cursor_x, 3);
+
+        type_str(&mut editor, " = [");
+        assert_eq!(editor.text, vec!["foo = []"]);
+        editor.delete_word_forward();
+        assert_eq!(editor.text, vec!["foo = "]);
+        assert_eq!(editor.cursor_x, 6);
+
+        editor.undo();
+        assert_eq!(editor.text, vec!["foo = []"]);
+
+        // Only pairs, and only with auto pairs on
+        editor.text = Rope::from(vec![String::from("a(b)")]);
+        editor.cursor_x = 2;
+        editor.delete_word_back();
+        assert_eq!(editor.text, vec!["ab)"]);
+
+        editor.auto_pairs = false;
+        editor.text = Rope::from(vec![String::from("f()")]);
+        editor.cursor_x = 2;
+        editor.delete_word_back();
+        assert_eq!(editor.text, vec!["f)"]);
+    }
+
+    #[test]
+    fn ctrl_backspace_and_delete_remove_words() {
+        let mut editor = Editor::new();
+        editor.text = Rope::from(vec![String::from("let value = 10;")]);
+        editor.cursor_x = 9;
+
+        editor.delete_word_back();
+        assert_eq!(editor.text, vec!["let  = 10;"]);
+        assert_eq!(editor.cursor_x, 4);
+
+        editor.delete_word_forward();
+        assert_eq!(editor.text, vec!["let  10;"]);
+
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.text, vec!["let value = 10;"]);
+    }
+
+    #[test]
+    fn delete_line_and_to_line_end() {
+        let mut editor = Editor::new();
+        editor.text = Rope::from(vec![String::from("one"), String::from("two"), String::from("three")]);
+        editor.cursor_x = 2;
+
+        editor.delete_line();
+        assert_eq!(editor.text, vec!["two", "three"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 2));
+
+        editor.delete_to_line_end();
+        assert_eq!(editor.text, vec!["tw", "three"]);
+        editor.delete_to_line_end();
+        assert_eq!(editor.text, vec!["twthree"]);
+
+        // The last line takes the break before it
+        editor.text = Rope::from(vec![String::from("one"), String::from("two")]);
+        (editor.cursor_y, editor.cursor_x) = (1, 3);
+        editor.delete_line();
+        assert_eq!(editor.text, vec!["one"]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 3));
+    }
+
+    #[test]
+    fn goto_line_clamps_and_centers() {
+        let mut editor = Editor::new();
+        editor.text = Rope::from((0..100).map(|i| format!("line {}", i)).collect::<Vec<_>>());
+        editor.viewport.rows = 20;
+
+        editor.goto_line(50, 100);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (50, 7));
+        assert_eq!(editor.viewport.top, 40);
+
+        editor.goto_line(99, 0);
+        assert_eq!(editor.viewport.top, 80);
+    }
 }
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::rope::Rope;
+
+// File handling.
+// Loads a file from disk into the editor lines
+// and writes them back exactly as they were read:
+//
+// - The line ending style is remembered ('\n' or "\r\n"),
+//   CRLF is only picked when *every* line break is CRLF,
+//   otherwise stray '\r' stay inside the line text
+//
+// - A leading UTF-8 BOM is stripped and written back
+//
+// - Whether the file ended with a line break is remembered,
+//   so a file without a final newline doesn't gain one
+
+// UTF-8 byte order mark
+const BOM: &str = "\u{feff}";
+
+// Line ending style of a file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    Lf,     // '\n'
+    CrLf    // "\r\n"
+}
+
+impl LineEnding {
+
+    // The actual bytes of the line ending
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n"
+        }
+    }
+
+}
+
+// On disk information of a file open in an editor
+#[derive(Clone, Debug)]
+pub struct TextFile {
+    pub path: PathBuf,              // Where the file lives
+    pub line_ending: LineEnding,    // Line ending used when writing
+    pub trailing_newline: bool,     // Did the file end with a line ending
+    pub bom: bool                   // Did the file start with a UTF-8 BOM
+}
+
+impl TextFile {
+
+    // Information for a file that does not exist yet
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: absolute(path),
+            line_ending: LineEnding::Lf,
+            trailing_newline: true,
+            bom: false
+        }
+    }
+
+    // File name for display purposes
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.to_string_lossy().into_owned())
+    }
+
+}
+
+// File errors, displayed in the console
+#[derive(Debug)]
+pub enum FileError {
+    NotFound(PathBuf),
+    PermissionDenied(PathBuf),
+    InvalidUtf8(PathBuf),
+    NoPath,                 // Scratch buffer written without a file name
+    Io(PathBuf, io::Error)
+}
+
+impl FileError {
+
+    // Map an io error to a file error for the given path
+    pub fn from_io(path: &Path, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound(path.to_path_buf()),
+            io::ErrorKind::PermissionDenied => FileError::PermissionDenied(path.to_path_buf()),
+            io::ErrorKind::InvalidData => FileError::InvalidUtf8(path.to_path_buf()),
+            _ => FileError::Io(path.to_path_buf(), error)
+        }
+    }
+
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::NotFound(path) => write!(f, "{}: file not found", path.display()),
+            FileError::PermissionDenied(path) => write!(f, "{}: permission denied", path.display()),
+            FileError::InvalidUtf8(path) => write!(f, "{}: not valid UTF-8", path.display()),
+            FileError::NoPath => write!(f, "no file name, use '?wf fname'"),
+            FileError::Io(path, error) => write!(f, "{}: {}", path.display(), error)
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+// Absolute version of a path, files keep pointing
+// to the same place when the working directory changes
+pub fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Read a file into lines.
+// The returned rope always has at least one line
+pub fn load(path: &Path) -> Result<(Rope, TextFile), FileError> {
+    let bytes = fs::read(path).map_err(|e| FileError::from_io(path, e))?;
+    let content = String::from_utf8(bytes).map_err(|_| FileError::InvalidUtf8(path.to_path_buf()))?;
+
+    let (lines, mut file) = split_lines(&content);
+    file.path = absolute(path);
+
+    Ok((Rope::from(lines), file))
+}
+
+// Create a new empty file on disk
+pub fn create(path: &Path) -> Result<TextFile, FileError> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| FileError::from_io(path, e))?;
+
+    Ok(TextFile::new(path))
+}
+
+// Write the lines to the file's path.
+// Returns the number of lines written
+pub fn save(file: &TextFile, lines: &Rope) -> Result<usize, FileError> {
+    fs::write(&file.path, join_lines(file, lines)).map_err(|e| FileError::from_io(&file.path, e))?;
+
+    Ok(lines.len())
+}
+
+// Where the undo history of a file is kept: in the user's data
+// directory, named after a hash of the file's path, so nothing
+// is left next to the file itself
+pub fn undo_path(file: &TextFile) -> Option<PathBuf> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in file.path.to_string_lossy().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    dirs::data_dir().map(|data| data.join("whiskey").join("undo").join(format!("{:016x}-{}", hash, file.name())))
+}
+
+// Read the saved undo history of a file, if there is one
+pub fn read_undo(file: &TextFile) -> Option<String> {
+    fs::read_to_string(undo_path(file)?).ok()
+}
+
+// Write the undo history of a file
+pub fn write_undo(file: &TextFile, history: &str) -> Result<(), FileError> {
+    let Some(path) = undo_path(file) else {
+        return Ok(());
+    };
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|e| FileError::from_io(directory, e))?;
+    }
+    fs::write(&path, history).map_err(|e| FileError::from_io(&path, e))
+}
+
+// Split file content into lines, detecting its format
+pub fn split_lines(content: &str) -> (Vec<String>, TextFile) {
+    let mut file = TextFile::new(Path::new(""));
+
+    let content = match content.strip_prefix(BOM) {
+        Some(rest) => {
+            file.bom = true;
+            rest
+        }
+        None => content
+    };
+
+    let breaks = content.matches('\n').count();
+    let crlf_breaks = content.matches("\r\n").count();
+    if breaks > 0 && breaks == crlf_breaks {
+        file.line_ending = LineEnding::CrLf;
+    }
+
+    file.trailing_newline = content.ends_with('\n');
+
+    let body = content
+        .strip_suffix(file.line_ending.as_str())
+        .unwrap_or(content);
+
+    let lines = body
+        .split(file.line_ending.as_str())
+        .map(String::from)
+        .collect();
+
+    (lines, file)
+}
+
+// Join lines back into the exact file content
+pub fn join_lines(file: &TextFile, lines: &Rope) -> String {
+    let mut content = String::new();
+
+    if file.bom {
+        content.push_str(BOM);
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            content.push_str(file.line_ending.as_str());
+        }
+        content.push_str(line);
+    }
+
+    if file.trailing_newline {
+        content.push_str(file.line_ending.as_str());
+    }
+
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write bytes to a file, load it and save it back, returning what ended up on disk
+    fn round_trip(name: &str, bytes: &[u8]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("whiskey-files-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+
+        let (lines, file) = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        save(&file, &lines).unwrap();
+
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        written
+    }
+
+    #[test]
+    fn files_are_written_back_byte_for_byte() {
+        let cases: [(&str, &[u8]); 9] = [
+            ("lf", b"one\ntwo\n"),
+            ("crlf", b"one\r\ntwo\r\n"),
+            ("mixed", b"one\r\ntwo\nthree\r\n"),
+            ("lf-no-newline", b"one\ntwo"),
+            ("crlf-no-newline", b"one\r\ntwo"),
+            ("bom", b"\xef\xbb\xbfone\ntwo\n"),
+            ("bom-crlf", b"\xef\xbb\xbfone\r\n"),
+            ("empty", b""),
+            ("newline", b"\n")
+        ];
+
+        for (name, bytes) in cases {
+            assert_eq!(round_trip(name, bytes), bytes, "{}", name);
+        }
+    }
+
+    #[test]
+    fn line_format_is_detected() {
+        let (lines, file) = split_lines("one\r\ntwo\r\n");
+        assert_eq!(lines, vec!["one", "two"]);
+        assert_eq!(file.line_ending, LineEnding::CrLf);
+        assert!(file.trailing_newline);
+
+        // Mixed endings stay LF, the '\r' stays in the line
+        let (lines, file) = split_lines("one\r\ntwo\nthree");
+        assert_eq!(lines, vec!["one\r", "two", "three"]);
+        assert_eq!(file.line_ending, LineEnding::Lf);
+        assert!(!file.trailing_newline);
+
+        let (lines, file) = split_lines("\u{feff}");
+        assert_eq!(lines, vec![""]);
+        assert!(file.bom);
+    }
+
+    #[test]
+    fn load_errors() {
+        let missing = std::env::temp_dir().join(format!("whiskey-files-{}-missing", std::process::id()));
+        assert!(matches!(load(&missing), Err(FileError::NotFound(path)) if path == missing));
+
+        let binary = std::env::temp_dir().join(format!("whiskey-files-{}-binary", std::process::id()));
+        fs::write(&binary, b"ok\xff\xfe").unwrap();
+        assert!(matches!(load(&binary), Err(FileError::InvalidUtf8(path)) if path == binary));
+        fs::remove_file(&binary).unwrap();
+
+        let denied = FileError::from_io(&missing, io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(&denied, FileError::PermissionDenied(path) if *path == missing));
+        assert_eq!(denied.to_string(), format!("{}: permission denied", missing.display()));
+
+        // Creating over an existing file is refused
+        let existing = std::env::temp_dir().join(format!("whiskey-files-{}-existing", std::process::id()));
+        fs::write(&existing, "").unwrap();
+        assert!(matches!(create(&existing), Err(FileError::Io(..))));
+        fs::remove_file(&existing).unwrap();
+    }
+}
@@ -0,0 +1,733 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::rope::Rope;
+
+// Undo/redo history.
+// Every change to the text is recorded as a reversible edit:
+// an insertion or deletion of some text at a (line, byte) position.
+// Text spanning multiple lines uses '\n' as the line separator.
+// Byte positions stay valid when a combining character merges
+// into the grapheme before it, cursor (line, column) positions don't
+//
+// Edits are grouped into steps, a step is what a single
+// undo/redo reverts/replays. Consecutive typing (or deleting)
+// at the cursor is merged into one step, moving the cursor
+// or typing a space after a word starts a new one.
+//
+// Steps are kept in a tree, undoing and then editing starts
+// a new branch instead of throwing the undone steps away.
+// Redo follows the branch that was visited last.
+//
+// Writing a file keeps its tree in the user's data directory,
+// the oldest states dropped past HISTORY_SIZE, and opening
+// the file again picks it up if the text is still the same
+
+// Most bytes a history kept for the next session takes
+const HISTORY_SIZE: usize = 1 << 20;
+
+// Position in the text, (line, byte) for edits and (line, column) for the cursor
+pub type Position = (usize, usize);
+
+// A single reversible change
+#[derive(Clone, Debug)]
+pub enum Edit {
+    Insert { at: Position, text: String },
+    Delete { at: Position, text: String }
+}
+
+// What kind of action produced a step,
+// only steps of the same kind are merged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EditKind {
+    Typing,     // Characters typed one at a time
+    Deleting,   // Backspace presses
+    Other       // Everything else, never merged
+}
+
+// Edits undone/redone together
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub edits: Vec<Edit>,   // Edits in the order they were applied
+    pub kind: EditKind,
+    pub before: Position,   // Cursor before the step
+    pub after: Position     // Cursor after the step
+}
+
+// A state of the text in the history tree,
+// reached by applying its step to its parent
+pub struct Node {
+    pub step: Step,
+    pub parent: usize,
+    pub children: Vec<usize>,       // Branches, oldest first
+    pub active: Option<usize>,      // Child redo goes to
+    pub time: u64                   // Seconds since the epoch of the last edit
+}
+
+pub struct History {
+    pub nodes: Vec<Node>,   // Node 0 is the text as it was opened, nodes are in creation order
+    pub current: usize,     // Node the text is at
+    pub saved: usize,       // Node the text was at when last written (or opened)
+    join: bool              // The next edit goes into the current step
+}
+
+impl History {
+
+    // History constructor
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                step: Step {
+                    edits: Vec::new(),
+                    kind: EditKind::Other,
+                    before: (0, 0),
+                    after: (0, 0)
+                },
+                parent: 0,
+                children: Vec::new(),
+                active: None,
+                time: now()
+            }],
+            current: 0,
+            saved: 0,
+            join: false
+        }
+    }
+
+    // Record an edit that was just applied.
+    // Undone steps stay in the tree as another branch
+    pub fn record(&mut self, edit: Edit, kind: EditKind, before: Position, after: Position) {
+        if std::mem::take(&mut self.join) || self.continues(&edit, kind, before) {
+            let node = &mut self.nodes[self.current];
+            node.step.edits.push(edit);
+            node.step.kind = kind;
+            node.step.after = after;
+            node.time = now();
+            return;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            step: Step {
+                edits: vec![edit],
+                kind,
+                before,
+                after
+            },
+            parent: self.current,
+            children: Vec::new(),
+            active: None,
+            time: now()
+        });
+
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(index);
+        parent.active = Some(index);
+
+        self.current = index;
+    }
+
+    // Can the edit be merged into the current step
+    fn continues(&self, edit: &Edit, kind: EditKind, before: Position) -> bool {
+        let last = &self.nodes[self.current];
+
+        // Steps with branches below them can't change anymore,
+        // neither can the one the file was written at
+        if self.current == 0 || self.current == self.saved || !last.children.is_empty() {
+            return false;
+        }
+
+        let last = &last.step;
+        if kind == EditKind::Other || last.kind != kind || last.after != before {
+            return false;
+        }
+
+        // A new word starts a new step
+        if let Edit::Insert { text, .. } = edit
+            && text.starts_with(char::is_whitespace) {
+            let typed_word = last.edits.last().is_some_and(|last_edit| match last_edit {
+                Edit::Insert { text, .. } => !text.ends_with(char::is_whitespace),
+                Edit::Delete { .. } => false
+            });
+
+            return !typed_word;
+        }
+
+        true
+    }
+
+    // Put the next edit into the current step, so they are undone together
+    pub fn join_next(&mut self) {
+        self.join = self.current != 0;
+    }
+
+    // Remember the current state as the one on disk
+    pub fn mark_saved(&mut self) {
+        self.saved = self.current;
+    }
+
+    // Is the text as it was when last written
+    pub fn at_saved(&self) -> bool {
+        self.current == self.saved
+    }
+
+    // Take the step to undo and move to the parent state
+    pub fn undo(&mut self) -> Option<Step> {
+        if self.current == 0 {
+            return None;
+        }
+
+        self.join = false;
+        let step = self.nodes[self.current].step.clone();
+        self.current = self.nodes[self.current].parent;
+
+        Some(step)
+    }
+
+    // Take the step to redo and move to the active child state
+    pub fn redo(&mut self) -> Option<Step> {
+        self.join = false;
+        let child = self.nodes[self.current].active?;
+        self.current = child;
+
+        Some(self.nodes[child].step.clone())
+    }
+
+    // Make the next branch the one redo follows.
+    // Returns the (branch, branch count), counting from 1
+    pub fn next_branch(&mut self) -> Option<(usize, usize)> {
+        let node = &mut self.nodes[self.current];
+        let active = node.active?;
+
+        let position = node.children.iter().position(|&child| child == active).unwrap_or(0);
+        let next = (position + 1) % node.children.len();
+        node.active = Some(node.children[next]);
+
+        Some((next + 1, node.children.len()))
+    }
+
+    // Latest state the text was in at the given time
+    pub fn state_at(&self, time: u64) -> usize {
+        self.nodes
+            .iter()
+            .rposition(|node| node.time <= time)
+            .unwrap_or(0)
+    }
+
+    // How to get from the current state to the target state:
+    // the number of undos up to the common ancestor and
+    // the states to redo into from there
+    pub fn path_to(&self, target: usize) -> (usize, Vec<usize>) {
+        let from = self.ancestors(self.current);
+        let mut to = self.ancestors(target);
+
+        // Both paths start at the root, drop what they share
+        let shared = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+
+        (from.len() - shared, to.split_off(shared))
+    }
+
+    // Path from the root down to a state, root excluded
+    fn ancestors(&self, mut node: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+
+        while node != 0 {
+            path.push(node);
+            node = self.nodes[node].parent;
+        }
+
+        path.reverse();
+        path
+    }
+
+    // Make redo from the parent state go to this state
+    pub fn select(&mut self, node: usize) {
+        let parent = self.nodes[node].parent;
+        self.nodes[parent].active = Some(node);
+    }
+
+    // Serialize the tree to be kept for the next session, dropping
+    // the oldest states when it would take more than HISTORY_SIZE.
+    // One node per 'n' line followed by its edits, text is escaped
+    pub fn serialize(&self, hash: u64) -> String {
+        match self.trimmed(HISTORY_SIZE) {
+            Some(trimmed) => trimmed.write(hash),
+            None => self.write(hash)
+        }
+    }
+
+    fn write(&self, hash: u64) -> String {
+        let mut out = format!("whiskey-undo 1\nhash {:x}\ncurrent {}\n", hash, self.current);
+
+        for node in self.nodes.iter() {
+            out.push_str(&node_lines(node));
+        }
+
+        out
+    }
+
+    // The history with its root moved down towards the current state
+    // until what is left under it takes at most 'limit' bytes.
+    // None when it already fits
+    fn trimmed(&self, limit: usize) -> Option<Self> {
+        // Size of each subtree, children always come after their parent
+        let mut sizes: Vec<usize> = self.nodes.iter().map(|node| node_lines(node).len()).collect();
+        for i in (1..self.nodes.len()).rev() {
+            sizes[self.nodes[i].parent] += sizes[i];
+        }
+
+        let root = std::iter::once(0)
+            .chain(self.ancestors(self.current))
+            .find(|&node| sizes[node] <= limit)
+            .unwrap_or(self.current);
+        if root == 0 {
+            return None;
+        }
+
+        // Old index to new index of the nodes under the new root
+        let mut kept: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut nodes: Vec<Node> = Vec::new();
+        for i in root..self.nodes.len() {
+            let parent = self.nodes[i].parent;
+            if i != root && kept[parent].is_none() {
+                continue;
+            }
+
+            kept[i] = Some(nodes.len());
+            let node = &self.nodes[i];
+            nodes.push(Node {
+                step: if i == root {
+                    Step { edits: Vec::new(), kind: EditKind::Other, before: node.step.after, after: node.step.after }
+                } else {
+                    node.step.clone()
+                },
+                parent: if i == root { 0 } else { kept[parent].unwrap_or(0) },
+                children: Vec::new(),
+                active: None,
+                time: node.time
+            });
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let Some(new) = kept[i] else {
+                continue;
+            };
+
+            nodes[new].active = node.active.and_then(|active| kept[active]);
+            if new != 0 {
+                let parent = nodes[new].parent;
+                nodes[parent].children.push(new);
+            }
+        }
+
+        Some(Self {
+            nodes,
+            current: kept[self.current].unwrap_or(0),
+            saved: kept[self.saved].unwrap_or(0),
+            join: false
+        })
+    }
+
+    // Read back a serialized tree, if it was written for the given
+    // text and is well formed: every step has to replay on it
+    pub fn deserialize(input: &str, text: &Rope) -> Option<Self> {
+        let hash = text_hash(text);
+        let mut lines = input.lines();
+
+        if lines.next()? != "whiskey-undo 1" {
+            return None;
+        }
+        if u64::from_str_radix(lines.next()?.strip_prefix("hash ")?, 16).ok()? != hash {
+            return None;
+        }
+        let current: usize = lines.next()?.strip_prefix("current ")?.parse().ok()?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        for line in lines {
+            let mut fields = line.splitn(4, ' ');
+            let tag = fields.next()?;
+
+            if tag == "n" {
+                let rest: Vec<&str> = line.split(' ').skip(1).collect();
+                if rest.len() != 8 {
+                    return None;
+                }
+
+                let number = |i: usize| rest[i].parse::<usize>().ok();
+                nodes.push(Node {
+                    step: Step {
+                        edits: Vec::new(),
+                        kind: match rest[3] {
+                            "t" => EditKind::Typing,
+                            "d" => EditKind::Deleting,
+                            _ => EditKind::Other
+                        },
+                        before: (number(4)?, number(5)?),
+                        after: (number(6)?, number(7)?)
+                    },
+                    parent: number(0)?,
+                    children: Vec::new(),
+                    active: match rest[1] {
+                        "-" => None,
+                        active => Some(active.parse().ok()?)
+                    },
+                    time: rest[2].parse().ok()?
+                });
+                continue;
+            }
+
+            let at = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+            let text = unescape(fields.next().unwrap_or(""));
+            let edit = match tag {
+                "i" => Edit::Insert { at, text },
+                "d" => Edit::Delete { at, text },
+                _ => return None
+            };
+            nodes.last_mut()?.step.edits.push(edit);
+        }
+
+        // Rebuild the children from the parents,
+        // parents always come before their children
+        for i in 1..nodes.len() {
+            let parent = nodes[i].parent;
+            if parent >= i {
+                return None;
+            }
+            nodes[parent].children.push(i);
+        }
+
+        if nodes.is_empty() || current >= nodes.len() {
+            return None;
+        }
+
+        if nodes.iter().any(|node| node.active.is_some_and(|active| !node.children.contains(&active))) {
+            return None;
+        }
+
+        let history = Self { nodes, current, saved: current, join: false };
+        history.replays(text).then_some(history)
+    }
+
+    // Check every step of the tree applies to the text: walk up from
+    // the current state to the root undoing, then through every branch
+    fn replays(&self, text: &Rope) -> bool {
+        let mut lines: Vec<String> = text.iter().cloned().collect();
+
+        let mut node = self.current;
+        while node != 0 {
+            if unapply(&mut lines, &self.nodes[node].step).is_none() {
+                return false;
+            }
+            node = self.nodes[node].parent;
+        }
+
+        // Depth first, false once a state is left
+        let mut stack: Vec<(usize, bool)> = self.nodes[0].children.iter().rev().map(|&child| (child, true)).collect();
+        while let Some((node, entering)) = stack.pop() {
+            let step = &self.nodes[node].step;
+
+            if !entering {
+                if unapply(&mut lines, step).is_none() {
+                    return false;
+                }
+                continue;
+            }
+
+            if !fits(&lines, step.before) || apply(&mut lines, step).is_none() || !fits(&lines, step.after) {
+                return false;
+            }
+
+            stack.push((node, false));
+            stack.extend(self.nodes[node].children.iter().rev().map(|&child| (child, true)));
+        }
+
+        true
+    }
+
+}
+
+// Seconds since the epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// Hash of the text, to check a saved
+// history still belongs to the file (FNV-1a)
+pub fn text_hash(text: &Rope) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for line in text.iter() {
+        for byte in line.bytes().chain(std::iter::once(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+}
+
+// A node and its edits as serialized, with the indices it has now
+fn node_lines(node: &Node) -> String {
+    let step = &node.step;
+    let kind = match step.kind {
+        EditKind::Typing => 't',
+        EditKind::Deleting => 'd',
+        EditKind::Other => 'o'
+    };
+    let active = node.active.map_or(String::from("-"), |active| active.to_string());
+
+    let mut out = format!("n {} {} {} {} {} {} {} {}\n",
+        node.parent, active, node.time, kind,
+        step.before.0, step.before.1, step.after.0, step.after.1);
+
+    for edit in step.edits.iter() {
+        let (tag, (y, x), text) = match edit {
+            Edit::Insert { at, text } => ('i', at, text),
+            Edit::Delete { at, text } => ('d', at, text)
+        };
+        out.push_str(&format!("{} {} {} {}\n", tag, y, x, escape(text)));
+    }
+
+    out
+}
+
+// Replay a step on lines, None when it doesn't fit them
+fn apply(lines: &mut Vec<String>, step: &Step) -> Option<()> {
+    for edit in step.edits.iter() {
+        match edit {
+            Edit::Insert { at, text } => insert(lines, *at, text)?,
+            Edit::Delete { at, text } => delete(lines, *at, text)?
+        }
+    }
+
+    Some(())
+}
+
+// Revert a step on lines, None when it doesn't fit them
+fn unapply(lines: &mut Vec<String>, step: &Step) -> Option<()> {
+    for edit in step.edits.iter().rev() {
+        match edit {
+            Edit::Insert { at, text } => delete(lines, *at, text)?,
+            Edit::Delete { at, text } => insert(lines, *at, text)?
+        }
+    }
+
+    Some(())
+}
+
+// Is a (line, column) cursor inside the lines
+fn fits(lines: &[String], (y, x): Position) -> bool {
+    lines.get(y).is_some_and(|line| x <= line.graphemes(true).count())
+}
+
+// Insert text at a (line, byte) position of the lines
+fn insert(lines: &mut Vec<String>, (y, x): Position, text: &str) -> Option<()> {
+    let line = lines.get_mut(y)?;
+    if !line.is_char_boundary(x) {
+        return None;
+    }
+
+    let rest = line.split_off(x);
+    let mut parts = text.split('\n');
+    line.push_str(parts.next().unwrap_or(""));
+
+    let mut last = y;
+    for part in parts {
+        last += 1;
+        lines.insert(last, part.to_string());
+    }
+    lines[last].push_str(&rest);
+
+    Some(())
+}
+
+// Delete text at a (line, byte) position of the lines,
+// if that text is what is there
+fn delete(lines: &mut Vec<String>, (y, x): Position, text: &str) -> Option<()> {
+    let parts: Vec<&str> = text.split('\n').collect();
+    let last = y + parts.len() - 1;
+    if last >= lines.len() || !lines[y].is_char_boundary(x) {
+        return None;
+    }
+
+    let matches = parts.iter().enumerate().all(|(i, part)| {
+        let line = if i == 0 { &lines[y][x..] } else { lines[y + i].as_str() };
+        if i == parts.len() - 1 { line.starts_with(part) } else { line == *part }
+    });
+    if !matches {
+        return None;
+    }
+
+    let end = if parts.len() == 1 { x + text.len() } else { parts[parts.len() - 1].len() };
+    let rest = lines[last][end..].to_string();
+    lines.drain(y + 1..=last);
+    lines[y].truncate(x);
+    lines[y].push_str(&rest);
+
+    Some(())
+}
+
+// Keep the text of an edit on a single line
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+// Reverse of escape
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Editor;
+
+    fn type_str(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.insert_char(c);
+        }
+    }
+
+    // "one two" typed, the second word undone and "three" typed instead
+    fn branched() -> Editor {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "one two");
+        editor.undo();
+        type_str(&mut editor, " three");
+        editor.new_line();
+        type_str(&mut editor, "é");
+        editor
+    }
+
+    #[test]
+    fn undo_then_typing_keeps_the_old_branch() {
+        let mut editor = branched();
+        assert_eq!(editor.text, vec!["one three", "é"]);
+
+        editor.undo();
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.text, vec!["one"]);
+        assert_eq!(editor.history.nodes[editor.history.current].children.len(), 2);
+
+        // Redo follows the newest branch, '?ub' switches to the other one
+        assert_eq!(editor.next_branch(), Some((1, 2)));
+        editor.redo();
+        assert_eq!(editor.text, vec!["one two"]);
+
+        editor.undo();
+        assert_eq!(editor.next_branch(), Some((2, 2)));
+        editor.redo();
+        assert_eq!(editor.text, vec!["one three"]);
+    }
+
+    #[test]
+    fn paths_between_states() {
+        let mut editor = branched();
+        let history = &editor.history;
+
+        // From the end of the newer branch to the end of the older one
+        let older = history.nodes[1].children[0];
+        assert_eq!(history.path_to(older), (3, vec![older]));
+        assert_eq!(history.path_to(history.current), (0, Vec::new()));
+
+        assert_eq!(history.state_at(0), 0);
+        assert_eq!(history.state_at(u64::MAX), history.nodes.len() - 1);
+
+        assert_eq!(editor.goto_time(0), 4);
+        assert_eq!(editor.text, vec![""]);
+        editor.goto_time(u64::MAX);
+        assert_eq!(editor.text, vec!["one three", "é"]);
+    }
+
+    #[test]
+    fn serialized_history_reads_back() {
+        let editor = branched();
+        let saved = editor.history.serialize(text_hash(&editor.text));
+        let history = History::deserialize(&saved, &editor.text).unwrap();
+
+        assert_eq!(history.current, editor.history.current);
+        assert_eq!(history.nodes.len(), editor.history.nodes.len());
+        assert_eq!(history.serialize(text_hash(&editor.text)), saved);
+
+        // Only for the text it was written for
+        assert!(History::deserialize(&saved, &Rope::from(vec![String::from("other")])).is_none());
+    }
+
+    #[test]
+    fn big_histories_are_trimmed() {
+        let mut editor = Editor::new();
+        for _ in 0..50 {
+            type_str(&mut editor, "word ");
+        }
+        editor.undo();
+        type_str(&mut editor, "last");
+
+        let history = &editor.history;
+        assert!(history.trimmed(1 << 20).is_none());
+
+        let trimmed = history.trimmed(200).unwrap();
+        assert!(trimmed.nodes.len() < history.nodes.len());
+        let saved = trimmed.write(text_hash(&editor.text));
+        assert!(saved.len() < 300);
+
+        // Starting from the text some steps back
+        let read = History::deserialize(&saved, &editor.text).unwrap();
+        assert_eq!(read.nodes.len(), trimmed.nodes.len());
+        assert_eq!(read.nodes[read.current].step.after, (editor.cursor_y, editor.cursor_x));
+
+        // Even the current step alone too big leaves just the current state
+        let trimmed = history.trimmed(0).unwrap();
+        assert_eq!((trimmed.nodes.len(), trimmed.current), (1, 0));
+        assert!(History::deserialize(&trimmed.write(text_hash(&editor.text)), &editor.text).is_some());
+    }
+
+    #[test]
+    fn malformed_history_is_rejected() {
+        let editor = branched();
+        let saved = editor.history.serialize(text_hash(&editor.text));
+        let read = |input: String| History::deserialize(&input, &editor.text);
+
+        assert!(read(saved.replacen("whiskey-undo 1", "whiskey-undo 2", 1)).is_none());
+        assert!(read(saved.replacen("current ", "current 9", 1)).is_none());
+        assert!(read(saved.lines().take(3).collect::<Vec<_>>().join("\n")).is_none());
+
+        // Redo going to a node that isn't a child
+        let node = saved.lines().find(|line| line.starts_with("n 0 1 ")).unwrap();
+        assert!(read(saved.replacen(node, &node.replacen("n 0 1 ", "n 0 3 ", 1), 1)).is_none());
+        assert!(read(saved.replacen(node, &node.replacen("n 0 1 ", "n 0 99 ", 1), 1)).is_none());
+
+        // Edits that don't fit the text
+        assert!(read(saved.replacen("i 0 0 o", "i 0 40 o", 1)).is_none());
+        assert!(read(saved.replacen("i 0 0 o", "i 5 0 o", 1)).is_none());
+        assert!(read(saved.replacen("i 1 0 é", "i 1 1 é", 1)).is_none());
+        // Deleting text that isn't there, on the branch that isn't current
+        assert!(read(saved.replacen("i 0 4 t", "d 0 4 t", 1)).is_none());
+
+        // Cursors outside the text
+        let node = saved.lines().filter(|line| line.starts_with("n 0 ")).nth(1).unwrap();
+        let fields: Vec<&str> = node.split(' ').collect();
+        let moved = format!("{} 7 7 {} {}", fields[..5].join(" "), fields[7], fields[8]);
+        assert!(read(saved.replacen(node, &moved, 1)).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::ops::Range;
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::rope::Rope;
+
+// Layout.
+// Maps the lines of the text to the rows drawn on screen.
+// Without soft wrapping every line is a single row, with it
+// long lines are broken over several rows, after a space
+// when possible, in the middle of a word when not.
+//
+// Widths are measured through a function so the layout
+// can be worked out (and tested) without a window or font
+
+// A row on screen, part of a line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub line: usize,    // Line the row belongs to
+    pub start: usize,   // First column of the row
+    pub end: usize,     // Column after the last one of the row
+    pub last: bool      // Is it the last row of its line
+}
+
+impl Row {
+
+    // Is the cursor column on this row.
+    // The column between two rows belongs to the second one,
+    // the end of the line to the last row
+    pub fn contains(&self, column: usize) -> bool {
+        column >= self.start && (column < self.end || (self.last && column == self.end))
+    }
+
+}
+
+// Soft wrapping settings
+#[derive(Clone)]
+pub struct Wrap {
+    pub width: f32,                         // Widest a row can be
+    pub measure: Rc<dyn Fn(&str) -> f32>    // Width of some text on screen
+}
+
+impl Wrap {
+
+    // Column ranges of the rows a line wraps into,
+    // there is always at least one, even for an empty line
+    pub fn rows(&self, line: &str) -> Vec<Range<usize>> {
+        let graphemes: Vec<&str> = line.graphemes(true).collect();
+
+        let mut rows = Vec::new();
+        let mut start = 0;
+        let mut width = 0.0;
+        let mut last_break = None; // Column after the last space on the row
+
+        for (column, grapheme) in graphemes.iter().enumerate() {
+            let grapheme_width = (self.measure)(grapheme);
+
+            // Spaces can hang past the edge
+            let space = grapheme.chars().all(char::is_whitespace);
+
+            if !space && width + grapheme_width > self.width && column > start {
+                let end = last_break.filter(|&end| end > start).unwrap_or(column);
+                rows.push(start..end);
+
+                // What comes after the break moves to the new row
+                width = graphemes[end..column].iter().map(|grapheme| (self.measure)(grapheme)).sum();
+                start = end;
+                last_break = None;
+            }
+
+            width += grapheme_width;
+            if space {
+                last_break = Some(column + 1);
+            }
+        }
+
+        rows.push(start..graphemes.len());
+        rows
+    }
+
+    // Index of the row a column is on
+    pub fn row_of(rows: &[Range<usize>], column: usize) -> usize {
+        rows.iter()
+            .position(|row| column < row.end)
+            .unwrap_or(rows.len() - 1)
+    }
+
+}
+
+// The rows to draw, starting at line 'top', 'count' rows at most
+pub fn rows(text: &Rope, top: usize, count: usize, wrap: Option<&Wrap>) -> Vec<Row> {
+    let mut rows = Vec::with_capacity(count);
+
+    for (line, content) in (top..).zip(text.iter_from(top)) {
+        if rows.len() >= count {
+            break;
+        }
+
+        let ranges = match wrap {
+            Some(wrap) => wrap.rows(content),
+            None => std::iter::once(0..content.graphemes(true).count()).collect()
+        };
+
+        let last = ranges.len() - 1;
+        for (i, range) in ranges.into_iter().enumerate() {
+            rows.push(Row {
+                line,
+                start: range.start,
+                end: range.end,
+                last: i == last
+            });
+        }
+    }
+
+    rows.truncate(count);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every grapheme is 1 wide
+    fn wrap(width: f32) -> Wrap {
+        Wrap {
+            width,
+            measure: Rc::new(|text: &str| text.graphemes(true).count() as f32)
+        }
+    }
+
+    #[test]
+    fn short_line_is_one_row() {
+        assert_eq!(wrap(10.0).rows("hello"), vec![0..5]);
+        assert_eq!(wrap(10.0).rows(""), vec![0..0]);
+    }
+
+    #[test]
+    fn breaks_after_spaces() {
+        assert_eq!(wrap(10.0).rows("hello there world"), vec![0..6, 6..12, 12..17]);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(wrap(4.0).rows("abcdefghij"), vec![0..4, 4..8, 8..10]);
+    }
+
+    #[test]
+    fn counts_graphemes_not_bytes() {
+        assert_eq!(wrap(3.0).rows("ééééé"), vec![0..3, 3..5]);
+    }
+
+    #[test]
+    fn row_of_column() {
+        let rows = wrap(10.0).rows("hello there world");
+
+        assert_eq!(Wrap::row_of(&rows, 0), 0);
+        assert_eq!(Wrap::row_of(&rows, 6), 1);
+        assert_eq!(Wrap::row_of(&rows, 17), 2);
+    }
+
+    #[test]
+    fn rows_from_top_line() {
+        let text = Rope::from(vec![
+            String::from("one"),
+            String::from("two three four"),
+            String::from("five")
+        ]);
+
+        let rows = rows(&text, 1, 3, Some(&wrap(6.0)));
+        assert_eq!(rows, vec![
+            Row { line: 1, start: 0, end: 4, last: false },
+            Row { line: 1, start: 4, end: 10, last: false },
+            Row { line: 1, start: 10, end: 14, last: true }
+        ]);
+
+        assert!(rows[2].contains(14));
+        assert!(!rows[0].contains(4));
+    }
+}
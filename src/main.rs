@@ -1,17 +1,43 @@
 use macroquad::prelude::*;
+use std::rc::Rc;
 use std::time::Instant;      // Timers for enter and backspace when is_key_down(...)
 
 mod editor;
-use editor::Editor; // Import the struct
+use editor::Editor;
 
 mod console;
-use console::Console;
+use console::{Console, Severity};
+
+mod files;
+
+mod buffers;
+use buffers::Buffers;
+
+mod history;
+
+mod recall;
+
+mod rope;
+
+mod viewport;
+
+mod layout;
+use layout::Wrap;
+
+mod clipboard;
+use clipboard::Clipboard;
+
+mod browser;
+
+mod trash;
+
+mod palette;
+
+mod themes;
+
+mod settings;
+use settings::Settings;
 
-// TODO: Add scrollable screen 
-// TODO: Add console system for goto_line, save_file, new_file, goto_dir etc commands
-// TODO: Add file handling system
-// TODO: Add palletes!
-// TODO: Add instant cursor movement with Ctrl
 // TODO: Add basic highlighting
 
 #[macroquad::main("whiskey")]
@@ -19,7 +45,24 @@ async fn main() {
     set_fullscreen(true); // Window is now fullscreen
 
     let mut console = Console::new();
-    let mut editor = Editor::new();
+    console.persist_history();
+    let mut clipboard = Clipboard::new();
+
+    // Open every file given on the command line in its own buffer,
+    // files that don't exist are created on the first write
+    let mut buffers = Buffers::new();
+    let mut settings = Settings::new();
+    for path in std::env::args().skip(1) {
+        if let Err(error) = buffers.open_or_new(std::path::Path::new(&path)) {
+            eprintln!("Whiskey: {}", error);
+            console.push_message(Severity::Error, format!("Error: {}", error));
+        }
+    }
+
+    // Palettes from theme files, checked for changes every second
+    console.use_themes(&mut settings);
+    let mut themes_timer = Instant::now();
+    let themes_rate = 1.0;
     
     // Top bar for info display
     let top_bar_margin:f32 = 30.0;
@@ -31,6 +74,10 @@ async fn main() {
     // Backspace press timer
     let mut backspace_timer = Instant::now();
     let mut backspace_held = false;
+
+    // Delete press timer
+    let mut delete_timer = Instant::now();
+    let mut delete_held = false;
     
     // Timer parameters
     let repeat_delay = 0.15;   // seconds before repeat starts
@@ -41,6 +88,11 @@ async fn main() {
     let mut cursor_visible = true;
     let cursor_rate = 0.45; // seconds per blink
 
+    // Scrolling
+    let scroll_margin = 3;  // lines kept visible above and below the cursor
+    let wheel_lines = 3;    // lines scrolled per mouse wheel notch
+    let wheel_pixels = 40.0; // pixels scrolled sideways per mouse wheel notch
+
     // Cursor movement
     let mut cursor_movement_timer = Instant::now();
     let mut cursor_movement_held = false;
@@ -49,16 +101,77 @@ async fn main() {
     let font_size = 22;
     let font: Font = load_ttf_font("assets/fonts/Courier Prime.ttf").await.unwrap();
 
+    // Text width on screen, for soft wrapping
+    let measure: Rc<dyn Fn(&str) -> f32> = {
+        let font = font.clone();
+        Rc::new(move |text: &str| measure_text(text, Some(&font), font_size, 1.0).width)
+    };
+
     loop {
-        clear_background(BLACK);
+        if themes_timer.elapsed().as_secs_f32() > themes_rate {
+            console.reload_themes(&mut settings);
+            themes_timer = Instant::now();
+        }
+
+        // Colours of this frame, '?p' switches them
+        let palette = settings.palette.clone();
+        clear_background(palette.background);
+
+        // Soft wrap at the right edge of the screen, when turned on
+        let text_width = screen_width() - 65.0 - 10.0;
+        buffers.current_mut().wrap = settings.soft_wrap.then(|| Wrap {
+            width: text_width,
+            measure: measure.clone()
+        });
+
+        if console.browser.is_some() { // Directory browser
+            for key in [KeyCode::Up, KeyCode::Down, KeyCode::Enter, KeyCode::Backspace, KeyCode::Escape] {
+                if is_key_pressed(key) {
+                    console.browse(key, &mut buffers);
+                }
+            }
+
+            // Don't type what was pressed once the browser closes
+            while get_char_pressed().is_some() {}
+        } else if !console.console_mode { // Text mode 
+            let editor = buffers.current_mut();
+
+            let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+            let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
 
-        if !console.console_mode { // Text mode 
             // Input handle
-            if let Some(c) = get_char_pressed() {
-                if !c.is_control() { // If it is a control character, do nothing
-                    editor.insert_char(c);
+            if let Some(c) = get_char_pressed()
+                && !c.is_control() // If it is a control character, do nothing
+                && !ctrl {         // Neither for shortcuts
+                editor.insert_char(c);
+            }
+
+            // Undo with CTRL + Z
+            // Redo with CTRL + SHIFT + Z or CTRL + Y
+            if ctrl && is_key_pressed(KeyCode::Z) {
+                if shift {
+                    editor.redo();
+                } else {
+                    editor.undo();
                 }
             }
+            if ctrl && is_key_pressed(KeyCode::Y) {
+                editor.redo();
+            }
+
+            // Copy, cut and paste with CTRL + C, X and V
+            if ctrl && is_key_pressed(KeyCode::C) {
+                let (text, line_wise) = editor.copy();
+                clipboard.set(text, line_wise);
+            }
+            if ctrl && is_key_pressed(KeyCode::X) {
+                let (text, line_wise) = editor.cut();
+                clipboard.set(text, line_wise);
+            }
+            if ctrl && is_key_pressed(KeyCode::V) {
+                let (text, line_wise) = clipboard.get();
+                editor.paste(&text, line_wise);
+            }
 
             // Tab key
             if is_key_pressed(KeyCode::Tab) {
@@ -84,10 +197,18 @@ async fn main() {
                 enter_held = false;
             }
             
-            // Backspace
+            // Backspace, a whole word with CTRL
+            let backspace = |editor: &mut Editor| {
+                if ctrl {
+                    editor.delete_word_back();
+                } else {
+                    editor.backspace();
+                }
+            };
+
             // Handle first press
             if is_key_pressed(KeyCode::Backspace) {
-                editor.backspace();
+                backspace(editor);
                 backspace_timer = Instant::now();
                 backspace_held = true;
             }
@@ -96,74 +217,154 @@ async fn main() {
             if is_key_down(KeyCode::Backspace) {
                 let elapsed = backspace_timer.elapsed().as_secs_f32();
                 if backspace_held && elapsed > repeat_delay {
-                    editor.backspace();
+                    backspace(editor);
                     backspace_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else {
                 backspace_held = false;
             }
+
+            // Delete, a whole word with CTRL
+            let delete = |editor: &mut Editor| {
+                if ctrl {
+                    editor.delete_word_forward();
+                } else {
+                    editor.delete();
+                }
+            };
+
+            // Handle first press
+            if is_key_pressed(KeyCode::Delete) {
+                delete(editor);
+                delete_timer = Instant::now();
+                delete_held = true;
+            }
+
+            // Handle held key
+            if is_key_down(KeyCode::Delete) {
+                let elapsed = delete_timer.elapsed().as_secs_f32();
+                if delete_held && elapsed > repeat_delay {
+                    delete(editor);
+                    delete_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
+                }
+            } else {
+                delete_held = false;
+            }
+
+            // Delete the line with CTRL + SHIFT + K,
+            // up to the end of the line with CTRL + K
+            if ctrl && is_key_pressed(KeyCode::K) {
+                if shift {
+                    editor.delete_line();
+                } else {
+                    editor.delete_to_line_end();
+                }
+            }
             
-            // Handle the cursor movement
+            // Handle the cursor movement, holding ctrl
+            // jumps further, holding shift extends the selection
+            let move_cursor = |editor: &mut Editor, key: KeyCode| {
+                if ctrl {
+                    editor.control_key_handle(key, shift);
+                } else if shift {
+                    editor.extend_selection(key);
+                } else {
+                    editor.move_cursor(key);
+                }
+            };
+
             if is_key_pressed(KeyCode::Up) {
-                editor.move_cursor(KeyCode::Up);
+                move_cursor(editor, KeyCode::Up);
                 cursor_movement_timer = Instant::now();
                 cursor_movement_held = true;
             }
             if is_key_pressed(KeyCode::Down) {
-                editor.move_cursor(KeyCode::Down);
+                move_cursor(editor, KeyCode::Down);
                 cursor_movement_timer = Instant::now();
                 cursor_movement_held = true;
             }
             if is_key_pressed(KeyCode::Left) {
-                editor.move_cursor(KeyCode::Left);
+                move_cursor(editor, KeyCode::Left);
                 cursor_movement_timer = Instant::now();
                 cursor_movement_held = true;
             }
             if is_key_pressed(KeyCode::Right) {
-                editor.move_cursor(KeyCode::Right);
+                move_cursor(editor, KeyCode::Right);
                 cursor_movement_timer = Instant::now();
                 cursor_movement_held = true;
             }
 
+            // Line, page and document jumps
+            for key in [KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown] {
+                if is_key_pressed(key) {
+                    move_cursor(editor, key);
+                }
+            }
+
             // Handle held key
             if is_key_down(KeyCode::Up) {
                 let elapsed = cursor_movement_timer.elapsed().as_secs_f32();
                 if cursor_movement_held && elapsed > repeat_delay {
-                    editor.move_cursor(KeyCode::Up);
+                    move_cursor(editor, KeyCode::Up);
                     cursor_movement_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else if is_key_down(KeyCode::Down) {
                 let elapsed = cursor_movement_timer.elapsed().as_secs_f32();
                 if cursor_movement_held && elapsed > repeat_delay {
-                    editor.move_cursor(KeyCode::Down);
+                    move_cursor(editor, KeyCode::Down);
                     cursor_movement_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else if is_key_down(KeyCode::Left) {
                 let elapsed = cursor_movement_timer.elapsed().as_secs_f32();
                 if cursor_movement_held && elapsed > repeat_delay {
-                    editor.move_cursor(KeyCode::Left);
+                    move_cursor(editor, KeyCode::Left);
                     cursor_movement_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else if is_key_down(KeyCode::Right) {
                 let elapsed = cursor_movement_timer.elapsed().as_secs_f32();
                 if cursor_movement_held && elapsed > repeat_delay {
-                    editor.move_cursor(KeyCode::Right);
+                    move_cursor(editor, KeyCode::Right);
                     cursor_movement_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else {
                 cursor_movement_held = false;
             }
         } else { // Console mode
-            if let Some(c) = get_char_pressed() {
-                if !c.is_control() { // If it is a control character, do nothing
-                    console.insert_char(c);
-                }
+            let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+            let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+
+            if let Some(c) = get_char_pressed()
+                && !c.is_control() // If it is a control character, do nothing
+                && !ctrl {         // Neither for shortcuts
+                console.insert_char(c);
+            }
+
+            // Same shortcuts as for the text
+            if ctrl && is_key_pressed(KeyCode::Z) {
+                console.edit(|input| if shift { input.redo() } else { input.undo() });
+            }
+            if ctrl && is_key_pressed(KeyCode::Y) {
+                console.edit(Editor::redo);
+            }
+            if ctrl && is_key_pressed(KeyCode::C) {
+                clipboard.set(console.copy(), false);
+            }
+            if ctrl && is_key_pressed(KeyCode::X) {
+                let text = console.cut();
+                clipboard.set(text, false);
+            }
+            if ctrl && is_key_pressed(KeyCode::V) {
+                let (text, _) = clipboard.get();
+                console.paste(&text);
+            }
+            if ctrl && is_key_pressed(KeyCode::K) {
+                console.edit(Editor::delete_to_line_end);
             }
 
-            // Backspace
+            // Backspace, a whole word with CTRL
             // Handle first press
             if is_key_pressed(KeyCode::Backspace) {
-                console.backspace();
+                console.backspace(ctrl);
                 backspace_timer = Instant::now();
                 backspace_held = true;
             }
@@ -172,15 +373,86 @@ async fn main() {
             if is_key_down(KeyCode::Backspace) {
                 let elapsed = backspace_timer.elapsed().as_secs_f32();
                 if backspace_held && elapsed > repeat_delay {
-                    console.backspace();
+                    console.backspace(ctrl);
                     backspace_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
                 }
             } else {
                 backspace_held = false;
             }
+
+            // Delete, a whole word with CTRL
+            // Handle first press
+            if is_key_pressed(KeyCode::Delete) {
+                console.delete(ctrl);
+                delete_timer = Instant::now();
+                delete_held = true;
+            }
+
+            // Handle held key
+            if is_key_down(KeyCode::Delete) {
+                let elapsed = delete_timer.elapsed().as_secs_f32();
+                if delete_held && elapsed > repeat_delay {
+                    console.delete(ctrl);
+                    delete_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
+                }
+            } else {
+                delete_held = false;
+            }
+
+            // Cursor movement along the command line
+            for key in [KeyCode::Left, KeyCode::Right, KeyCode::Home, KeyCode::End] {
+                if is_key_pressed(key) {
+                    console.move_cursor(key, ctrl, shift);
+                    cursor_movement_timer = Instant::now();
+                    cursor_movement_held = true;
+                }
+            }
+
+            // Handle held key
+            let held = [KeyCode::Left, KeyCode::Right].into_iter().find(|key| is_key_down(*key));
+            if let Some(key) = held {
+                let elapsed = cursor_movement_timer.elapsed().as_secs_f32();
+                if cursor_movement_held && elapsed > repeat_delay {
+                    console.move_cursor(key, ctrl, shift);
+                    cursor_movement_timer = Instant::now() - std::time::Duration::from_secs_f32(repeat_rate);
+                }
+            } else {
+                cursor_movement_held = false;
+            }
             
             if is_key_pressed(KeyCode::Enter) { 
-                console.execute();
+                if console.search.is_some() {
+                    console.accept_search();
+                } else {
+                    console.execute(&mut buffers, &mut settings);
+                }
+            }
+
+            // Complete with Tab, SHIFT + Tab goes back through the candidates
+            if is_key_pressed(KeyCode::Tab) {
+                console.complete(is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift));
+            }
+
+            // Commands run before with Up/Down, search them with CTRL + R
+            if is_key_pressed(KeyCode::Up) {
+                console.recall_previous();
+            }
+            if is_key_pressed(KeyCode::Down) {
+                console.recall_next();
+            }
+            if ctrl && is_key_pressed(KeyCode::R) {
+                console.reverse_search();
+            }
+            if is_key_pressed(KeyCode::Escape) {
+                console.cancel_search();
+            }
+
+            // Scroll the log back and forth
+            if is_key_pressed(KeyCode::PageUp) {
+                console.page_log(true);
+            }
+            if is_key_pressed(KeyCode::PageDown) {
+                console.page_log(false);
             }
         }
 
@@ -189,45 +461,162 @@ async fn main() {
             console.console_mode_switch();
         }
 
-        for (i, line) in editor.text.iter().enumerate() {
-            // Draw line number in gutter
+        // Rows of text that fit on screen, above the console when it is open
+        let text_height = screen_height() - top_bar_margin - if console.console_mode { console::CONSOLE_HEIGHT } else { 0.0 };
+        let rows = ((text_height - 5.0) / font_size as f32).max(1.0) as usize;
+
+        // Scroll with the mouse wheel, otherwise follow the cursor
+        let editor = buffers.current_mut();
+        let lines = editor.text.len();
+        let cursor = (editor.cursor_y, editor.cursor_x);
+        let (wheel_x, wheel) = mouse_wheel();
+        let over_console = console.console_mode && mouse_position().1 > screen_height() - console::CONSOLE_HEIGHT;
+        if wheel != 0.0 && over_console {
+            console.scroll_log(wheel.signum() as isize);
+        } else if wheel != 0.0 {
+            editor.viewport.scroll(-wheel.signum() as isize * wheel_lines, rows, lines);
+        }
+        if wheel_x != 0.0 && editor.wrap.is_none() {
+            editor.viewport.scroll_x(-wheel_x.signum() * wheel_pixels);
+        }
+        editor.viewport.rows = rows;
+        editor.viewport.follow(editor.cursor_y, rows, scroll_margin, lines);
+
+        if editor.wrap.is_some() {
+            // Wrapped lines take more than one row,
+            // make sure the cursor's row is on screen too
+            let (text, wrap) = (&editor.text, editor.wrap.as_ref());
+            editor.viewport.follow_wrapped(cursor, rows, scroll_margin, |top| {
+                layout::rows(text, top, rows, wrap)
+                    .iter()
+                    .position(|row| row.line == cursor.0 && row.contains(cursor.1))
+            });
+            editor.viewport.left = 0.0;
+        } else {
+            // Same for long lines, sideways
+            let caret = measure_text(&editor.text[editor.cursor_y][..editor.cursor_byte()], Some(&font), font_size, 1.0).width;
+            let caret_margin = measure_text("    ", Some(&font), font_size, 1.0).width;
+            editor.viewport.follow_x(cursor, caret, text_width, caret_margin);
+        }
+
+        let editor = buffers.current();
+        let left = editor.viewport.left;
+        let selection = editor.selection();
+        let mut cursor_position = None;
+
+        // Screen y of the top of a row
+        let row_top = |row: usize| top_bar_margin + 5.0 + row as f32 * font_size as f32;
+
+        // Only draw the rows on screen
+        for (r, row) in layout::rows(&editor.text, editor.viewport.top, rows, editor.wrap.as_ref()).iter().enumerate() {
+            let line = &editor.text[row.line];
+            let from = editor::byte_index(line, row.start);
+            let to = editor::byte_index(line, row.end);
+
+            // Screen x of a column on this row
+            let column_x = |column: usize| {
+                65.0 - left + measure_text(&line[from..editor::byte_index(line, column)], Some(&font), font_size, 1.0).width
+            };
+
+            // Highlight the selection behind the text
+            if let Some((start, end)) = selection {
+                let selected_from = start.max((row.line, row.start));
+                let selected_to = end.min((row.line, row.end));
+
+                if selected_from <= selected_to {
+                    let x = column_x(selected_from.1);
+                    let mut width = column_x(selected_to.1) - x;
+
+                    // Show the selected line break
+                    if row.last && end.0 > row.line {
+                        width += measure_text(" ", Some(&font), font_size, 1.0).width;
+                    }
+
+                    draw_rectangle(x, row_top(r), width, font_size as f32, palette.selection);
+                }
+            }
+
+            // Draw the actual text
             draw_text_ex(
-                &format!("{}", i + 1),
-                5.0, // left margin for line numbers
-                top_bar_margin + 20.0 + i as f32 * font_size as f32, // same y as the text
+                &line[from..to],
+                65.0 - left,                // Shift text to the right to leave space for numbers
+                row_top(r) + 15.0,
                 TextParams {
                     font: Some(&font),
                     font_size,
-                    color: WHITE,
+                    color: palette.text,
                     ..Default::default()
                 },
             );
 
-            // Text/line seperator
-            draw_line(
+            // Clip the text scrolled under the gutter
+            draw_rectangle(0.0, row_top(r), 60.0, font_size as f32, palette.background);
+
+            // Mark lines continuing past the left or right edge
+            if editor.wrap.is_none() {
+                let line_width = measure_text(line, Some(&font), font_size, 1.0).width;
+                let marker_width = measure_text(">", Some(&font), font_size, 1.0).width;
+                let marker_params = TextParams {
+                    font: Some(&font),
+                    font_size,
+                    color: palette.dim,
+                    ..Default::default()
+                };
+                if left > 0.0 && line_width > 0.0 {
+                    draw_rectangle(60.0, row_top(r), marker_width + 2.0, font_size as f32, palette.background);
+                    draw_text_ex("<", 61.0, row_top(r) + 15.0, marker_params.clone());
+                }
+                if 65.0 - left + line_width > screen_width() {
+                    let marker_x = screen_width() - marker_width - 2.0;
+                    draw_rectangle(marker_x, row_top(r), marker_width + 2.0, font_size as f32, palette.background);
+                    draw_text_ex(">", marker_x + 1.0, row_top(r) + 15.0, marker_params);
+                }
+            }
+
+            // Draw line number in gutter, only on the first row of a wrapped line
+            if row.start == 0 {
+                draw_text_ex(
+                    &format!("{}", row.line + 1),
+                    5.0, // left margin for line numbers
+                    row_top(r) + 15.0, // same y as the text
+                    TextParams {
+                        font: Some(&font),
+                        font_size,
+                        color: palette.gutter,
+                        ..Default::default()
+                    },
+                );
+            }
+
+            if row.line == editor.cursor_y && row.contains(editor.cursor_x) {
+                cursor_position = Some((column_x(editor.cursor_x), row_top(r)));
+            }
+        }
+
+        // Text/line seperator
+        draw_line(
             60.0,                                                 // x1: gutter separator
             top_bar_margin,                                       // y1: top of line
             60.0,                                                 // x2: same x for vertical line
             screen_height(),                                      // y2: bottom of line
             1.0,
-            WHITE
+            palette.line
         );
-        
-            // Draw the actual text
-            draw_text_ex(
-                line.as_str(),
-                65.0,                       // Shift text to the right to leave space for numbers
-                top_bar_margin + 20.0 + i as f32 * font_size as f32,
-                TextParams {
-                    font: Some(&font),
-                    font_size,
-                    color: WHITE,
-                    ..Default::default()
-                },
-            );
 
-            // Top bar line, display info on top of it 
-            draw_line(0.0, top_bar_margin, screen_width(), top_bar_margin, 1.0, WHITE);
+        // Top bar line, display info on top of it 
+        draw_line(0.0, top_bar_margin, screen_width(), top_bar_margin, 1.0, palette.line);
+
+        // Display the open buffers on the top bar
+        buffers.render_tabs(top_bar_margin, &palette);
+
+        // And the working directory, left of the mode
+        let directory = console::working_directory();
+        let directory_width = measure_text(&directory, None, 20, 1.0).width;
+        draw_text(&directory, screen_width() - 150.0 - directory_width, 20.0, 20.0, palette.dim);
+
+        // The directory browser goes over the text
+        if let Some(browser) = &mut console.browser {
+            browser.render(&font, font_size, top_bar_margin + 1.0, top_bar_margin + text_height, &palette);
         }
 
         // Cursor blink timer
@@ -237,32 +626,16 @@ async fn main() {
             cursor_timer = Instant::now();    // reset timer
         }
         
-        console.render_console();
+        console.render_console(&palette);
 
         // Render cursor
-        if cursor_visible && !console.console_mode { // Text mode
-            let cursor_x = 60.0
-                + measure_text(&editor.text[editor.cursor_y][..editor.cursor_x], Some(&font), font_size, 1.0).width +
-                 5.0;
-
-            let cursor_y = top_bar_margin + 
-                25.0 +                          // File lines margin 
-                editor.cursor_y as f32 *
-                font_size as f32;
-
-            draw_rectangle(cursor_x, cursor_y - font_size as f32, font_size as f32 / 6.0, font_size as f32, WHITE);
-        } else if cursor_visible {              // Console mode
-            let cursor_x = 5.0
-                + measure_text(&editor.text[editor.cursor_y][..editor.cursor_x], Some(&font), font_size, 1.0).width + 
-                5.0; // Padding
-
-            let cursor_y = 
-                screen_height() - 
-                console::CONSOLE_HEIGHT + 
-                font_size as f32 + 
-                5.0;                    // Padding
-
-            draw_rectangle(cursor_x, cursor_y - font_size as f32, font_size as f32 / 6.0, font_size as f32, WHITE);
+        if cursor_visible && !console.console_mode && console.browser.is_none() { // Text mode
+            if let Some((cursor_x, cursor_y)) = cursor_position {
+                draw_rectangle(cursor_x, cursor_y, font_size as f32 / 6.0, font_size as f32, palette.cursor);
+            }
+        } else if cursor_visible && console.console_mode { // Console mode
+            let (cursor_x, cursor_y, height) = console.cursor_position();
+            draw_rectangle(cursor_x, cursor_y, font_size as f32 / 6.0, height, palette.cursor);
         }
 
         next_frame().await;
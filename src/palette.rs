@@ -0,0 +1,252 @@
+use macroquad::prelude::*;
+
+// Palettes.
+// Every colour the editor draws with comes from the current palette,
+// switched with '?p pname'. 'dark' is the one the editor starts with.
+// Besides the built-in ones, palettes are loaded from theme files
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub background: Color,          // Behind the text
+    pub text: Color,
+    pub dim: Color,                 // Secondary text: directory, hints, markers
+    pub gutter: Color,              // Line numbers
+    pub line: Color,                // Separator lines of the top bar, gutter and tabs
+    pub cursor: Color,
+    pub selection: Color,           // Behind selected text
+    pub console_background: Color,
+    pub console_text: Color,        // Command line
+    pub info: Color,                // Log messages, by severity
+    pub warning: Color,
+    pub error: Color,
+    pub syntax: Syntax
+}
+
+// Colours of the syntax tokens
+#[allow(dead_code)] // Read once the text gets highlighted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Syntax {
+    pub keyword: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub function: Color,
+    pub type_name: Color
+}
+
+impl Palette {
+
+    // Palettes that come with the editor
+    pub fn builtin() -> Vec<Palette> {
+        vec![Self::dark(), Self::light(), Self::solarized(), Self::gruvbox()]
+    }
+
+    // Built-in palette with a given name
+    pub fn find(name: &str) -> Option<Palette> {
+        Self::builtin().into_iter().find(|palette| palette.name == name)
+    }
+
+    // White on black
+    fn dark() -> Self {
+        Self {
+            name: String::from("dark"),
+            background: BLACK,
+            text: WHITE,
+            dim: GRAY,
+            gutter: WHITE,
+            line: WHITE,
+            cursor: WHITE,
+            selection: Color::new(0.25, 0.35, 0.6, 1.0),
+            console_background: BLACK,
+            console_text: WHITE,
+            info: LIGHTGRAY,
+            warning: YELLOW,
+            error: RED,
+            syntax: Syntax {
+                keyword: rgb(0xc678dd),
+                string: rgb(0x98c379),
+                number: rgb(0xd19a66),
+                comment: rgb(0x7f848e),
+                function: rgb(0x61afef),
+                type_name: rgb(0xe5c07b)
+            }
+        }
+    }
+
+    // Dark on white
+    fn light() -> Self {
+        Self {
+            name: String::from("light"),
+            background: rgb(0xfafafa),
+            text: rgb(0x202020),
+            dim: rgb(0x808080),
+            gutter: rgb(0x606060),
+            line: rgb(0x202020),
+            cursor: rgb(0x202020),
+            selection: rgb(0xb4d5fe),
+            console_background: rgb(0xeeeeee),
+            console_text: rgb(0x202020),
+            info: rgb(0x505050),
+            warning: rgb(0xb58900),
+            error: rgb(0xd02020),
+            syntax: Syntax {
+                keyword: rgb(0xa626a4),
+                string: rgb(0x50a14f),
+                number: rgb(0x986801),
+                comment: rgb(0xa0a1a7),
+                function: rgb(0x4078f2),
+                type_name: rgb(0xc18401)
+            }
+        }
+    }
+
+    // Solarized dark
+    fn solarized() -> Self {
+        Self {
+            name: String::from("solarized"),
+            background: rgb(0x002b36),
+            text: rgb(0x839496),
+            dim: rgb(0x586e75),
+            gutter: rgb(0x586e75),
+            line: rgb(0x586e75),
+            cursor: rgb(0x93a1a1),
+            selection: rgb(0x274642),
+            console_background: rgb(0x073642),
+            console_text: rgb(0x93a1a1),
+            info: rgb(0x93a1a1),
+            warning: rgb(0xb58900),
+            error: rgb(0xdc322f),
+            syntax: Syntax {
+                keyword: rgb(0x859900),
+                string: rgb(0x2aa198),
+                number: rgb(0xd33682),
+                comment: rgb(0x586e75),
+                function: rgb(0x268bd2),
+                type_name: rgb(0xb58900)
+            }
+        }
+    }
+
+    // Gruvbox dark
+    fn gruvbox() -> Self {
+        Self {
+            name: String::from("gruvbox"),
+            background: rgb(0x282828),
+            text: rgb(0xebdbb2),
+            dim: rgb(0x928374),
+            gutter: rgb(0x7c6f64),
+            line: rgb(0x665c54),
+            cursor: rgb(0xebdbb2),
+            selection: rgb(0x504945),
+            console_background: rgb(0x1d2021),
+            console_text: rgb(0xebdbb2),
+            info: rgb(0xd5c4a1),
+            warning: rgb(0xfabd2f),
+            error: rgb(0xfb4934),
+            syntax: Syntax {
+                keyword: rgb(0xfb4934),
+                string: rgb(0xb8bb26),
+                number: rgb(0xd3869b),
+                comment: rgb(0x928374),
+                function: rgb(0xfabd2f),
+                type_name: rgb(0x8ec07c)
+            }
+        }
+    }
+
+    // Colour with a given key, as theme files name them
+    pub fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        Some(match key {
+            "background" => &mut self.background,
+            "text" => &mut self.text,
+            "dim" => &mut self.dim,
+            "gutter" => &mut self.gutter,
+            "line" => &mut self.line,
+            "cursor" => &mut self.cursor,
+            "selection" => &mut self.selection,
+            "console_background" => &mut self.console_background,
+            "console_text" => &mut self.console_text,
+            "info" => &mut self.info,
+            "warning" => &mut self.warning,
+            "error" => &mut self.error,
+            _ => return None
+        })
+    }
+
+}
+
+impl Syntax {
+
+    // Colour of a token kind, as theme files name them
+    pub fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        Some(match key {
+            "keyword" => &mut self.keyword,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "comment" => &mut self.comment,
+            "function" => &mut self.function,
+            "type_name" => &mut self.type_name,
+            _ => return None
+        })
+    }
+
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+// Opaque colour from a 0xRRGGBB value
+pub const fn rgb(hex: u32) -> Color {
+    Color::new(
+        ((hex >> 16) & 0xff) as f32 / 255.0,
+        ((hex >> 8) & 0xff) as f32 / 255.0,
+        (hex & 0xff) as f32 / 255.0,
+        1.0
+    )
+}
+
+// Colour from '#rrggbb' or '#rrggbbaa', the '#' can be left out
+pub fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some(rgb(value)),
+        8 => Some(Color { a: (value & 0xff) as f32 / 255.0, ..rgb(value >> 8) }),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_palettes_are_found_by_name() {
+        let names: Vec<String> = Palette::builtin().into_iter().map(|palette| palette.name).collect();
+        assert_eq!(names, vec!["dark", "light", "solarized", "gruvbox"]);
+
+        assert_eq!(Palette::find("light").unwrap().background, rgb(0xfafafa));
+        assert_eq!(Palette::default(), Palette::find("dark").unwrap());
+        assert!(Palette::find("neon").is_none());
+    }
+
+    #[test]
+    fn colours_from_hex() {
+        assert_eq!(rgb(0xff0000), Color::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgb(0x000000), BLACK);
+
+        assert_eq!(parse_color("#ff0000"), Some(rgb(0xff0000)));
+        assert_eq!(parse_color("00ff00"), Some(rgb(0x00ff00)));
+        assert_eq!(parse_color("#0000ff80"), Some(Color::new(0.0, 0.0, 1.0, 128.0 / 255.0)));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#+fffff"), None);
+        assert_eq!(parse_color("red"), None);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+
+// Command history.
+// The commands run in the console, oldest first, kept as a ring
+// of the last RECALL_SIZE ones. Up/Down walk through them, the line
+// being typed when browsing started comes back at the end.
+//
+// When opened from a file every new command is written back to it,
+// so the history carries over to the next session
+
+// Most commands kept
+const RECALL_SIZE: usize = 1000;
+
+pub struct Recall {
+    entries: VecDeque<String>,  // Commands run, oldest first
+    position: Option<usize>,    // Entry shown while browsing, None for the line being typed
+    draft: String,              // Line being typed when browsing started
+    path: Option<PathBuf>       // File the history is kept in
+}
+
+impl Recall {
+
+    // Empty history kept in memory only
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            position: None,
+            draft: String::new(),
+            path: None
+        }
+    }
+
+    // History kept in a file, loading what is already there
+    pub fn open(path: PathBuf) -> Self {
+        let mut recall = Self::new();
+
+        if let Ok(text) = fs::read_to_string(&path) {
+            for line in text.lines().filter(|line| !line.is_empty()) {
+                recall.add(line.to_string());
+            }
+        }
+
+        recall.path = Some(path);
+        recall
+    }
+
+    // Where the history is kept between sessions,
+    // in the user's config directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config| config.join("whiskey").join("history"))
+    }
+
+    // Number of commands kept
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Command at an index, oldest first
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    // Remember a command that was run and stop browsing.
+    // Repeating the last command doesn't add it again
+    pub fn push(&mut self, command: &str) {
+        self.position = None;
+
+        let command = command.trim();
+        if command.is_empty() || command.contains('\n') {
+            return;
+        }
+
+        if self.entries.back().is_some_and(|last| last == command) {
+            return;
+        }
+
+        self.add(command.to_string());
+        self.save();
+    }
+
+    // Add an entry, dropping the oldest one when full
+    fn add(&mut self, command: String) {
+        self.entries.push_back(command);
+        if self.entries.len() > RECALL_SIZE {
+            self.entries.pop_front();
+        }
+    }
+
+    // Write the history to its file, if it has one.
+    // Losing the history is not worth bothering the user about
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        let mut text = self.entries.iter().map(String::as_str).collect::<Vec<_>>().join("\n");
+        text.push('\n');
+        let _ = fs::write(path, text);
+    }
+
+    // The command before the one shown (Up).
+    // 'line' is what is typed right now, kept for when browsing gets back down
+    pub fn previous(&mut self, line: &str) -> Option<&str> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = line.to_string();
+                self.entries.len() - 1
+            }
+            Some(position) => position.saturating_sub(1)
+        };
+
+        self.position = Some(position);
+        self.get(position)
+    }
+
+    // The command after the one shown (Down),
+    // the line that was being typed after the newest one
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position?;
+
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            self.get(position + 1)
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+
+    // Newest command before 'before' containing 'query'
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .take(before)
+            .rposition(|entry| entry.contains(query))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recall(commands: &[&str]) -> Recall {
+        let mut recall = Recall::new();
+        for command in commands {
+            recall.push(command);
+        }
+        recall
+    }
+
+    #[test]
+    fn browse_back_and_forth() {
+        let mut recall = recall(&["?bl", "?wf", "?l 10"]);
+
+        assert_eq!(recall.previous("?u"), Some("?l 10"));
+        assert_eq!(recall.previous("?l 10"), Some("?wf"));
+        assert_eq!(recall.previous("?wf"), Some("?bl"));
+        assert_eq!(recall.previous("?bl"), Some("?bl"));
+
+        assert_eq!(recall.next(), Some("?wf"));
+        assert_eq!(recall.next(), Some("?l 10"));
+        assert_eq!(recall.next(), Some("?u"));
+        assert_eq!(recall.next(), None);
+    }
+
+    #[test]
+    fn skips_empty_and_repeated_commands() {
+        let recall = recall(&["?bl", "  ", "?bl", "?bn", "?bl"]);
+
+        assert_eq!(recall.len(), 3);
+        assert_eq!(recall.get(2), Some("?bl"));
+    }
+
+    #[test]
+    fn keeps_the_newest_commands() {
+        let mut recall = Recall::new();
+        for i in 0..RECALL_SIZE + 5 {
+            recall.push(&format!("?l {}", i));
+        }
+
+        assert_eq!(recall.len(), RECALL_SIZE);
+        assert_eq!(recall.get(0), Some("?l 5"));
+    }
+
+    #[test]
+    fn reverse_search() {
+        let recall = recall(&["?l 10", "notes.txt -w", "?l 20", "?bl"]);
+
+        assert_eq!(recall.search("?l", recall.len()), Some(2));
+        assert_eq!(recall.search("?l", 2), Some(0));
+        assert_eq!(recall.search("?l", 0), None);
+        assert_eq!(recall.search("nothing", recall.len()), None);
+    }
+
+    #[test]
+    fn persists_to_a_file() {
+        let path = std::env::temp_dir().join(format!("whiskey-recall-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut recall = Recall::open(path.clone());
+        recall.push("?wf");
+        recall.push("?l 12");
+
+        let reopened = Recall::open(path.clone());
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened.get(1), Some("?l 12"));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+use std::ops::{Bound, Index, IndexMut, RangeBounds};
+
+// Text storage.
+// A rope of lines: the lines are kept in small leaves
+// instead of one big vector, so splitting and merging lines
+// only shifts the lines of a single leaf around, no matter
+// how big the file is.
+//
+// Each line is still a plain String, so editing inside
+// a line works on it directly through indexing.
+//
+// Full leaves are split in two, leaves down to a quarter full are
+// merged into a neighbour when the two fit in one leaf, so deleting
+// most of a big file doesn't leave it in a trail of tiny leaves.
+// Finding a line walks the leaves one by one, that is
+// lines / LEAF_SIZE steps, fine for files up to millions of lines
+
+// Most lines a leaf holds before it is split in two
+const LEAF_SIZE: usize = 512;
+
+pub struct Rope {
+    leaves: Vec<Vec<String>>,   // Never empty, only a lone leaf may be empty
+    len: usize                  // Total number of lines
+}
+
+impl Rope {
+
+    // Rope constructor, a single empty line
+    pub fn new() -> Self {
+        Self::from(vec![String::new()])
+    }
+
+    // Number of lines
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Leaf holding a line and the line's index inside it.
+    // One past the last line maps to the end of the last leaf
+    fn locate(&self, mut index: usize) -> (usize, usize) {
+        for (leaf, lines) in self.leaves.iter().enumerate() {
+            if index < lines.len() {
+                return (leaf, index);
+            }
+            index -= lines.len();
+        }
+
+        let last = self.leaves.len() - 1;
+        (last, self.leaves[last].len() + index)
+    }
+
+    // Line at an index, if there is one
+    pub fn get(&self, index: usize) -> Option<&String> {
+        if index >= self.len {
+            return None;
+        }
+
+        let (leaf, offset) = self.locate(index);
+        self.leaves[leaf].get(offset)
+    }
+
+    // Line at an index for editing, if there is one
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut String> {
+        if index >= self.len {
+            return None;
+        }
+
+        let (leaf, offset) = self.locate(index);
+        self.leaves[leaf].get_mut(offset)
+    }
+
+    // Insert a line before the given index,
+    // the index may be one past the last line
+    pub fn insert(&mut self, index: usize, line: String) {
+        assert!(index <= self.len, "line index {} out of bounds ({} lines)", index, self.len);
+
+        let (leaf, offset) = self.locate(index);
+        self.leaves[leaf].insert(offset, line);
+        self.len += 1;
+
+        // Split a full leaf in halves
+        if self.leaves[leaf].len() > LEAF_SIZE {
+            let half = self.leaves[leaf].len() / 2;
+            let upper = self.leaves[leaf].split_off(half);
+            self.leaves.insert(leaf + 1, upper);
+        }
+    }
+
+    // Remove a line and return it
+    pub fn remove(&mut self, index: usize) -> String {
+        assert!(index < self.len, "line index {} out of bounds ({} lines)", index, self.len);
+
+        let (leaf, offset) = self.locate(index);
+        let line = self.leaves[leaf].remove(offset);
+        self.len -= 1;
+
+        self.merge(leaf);
+
+        line
+    }
+
+    // Merge a leaf into the next or previous one when it
+    // is down to a quarter full and the two fit together
+    fn merge(&mut self, leaf: usize) {
+        if self.leaves[leaf].len() > LEAF_SIZE / 4 {
+            return;
+        }
+
+        let fits = |other: usize| self.leaves[leaf].len() + self.leaves[other].len() <= LEAF_SIZE;
+        let first = if leaf + 1 < self.leaves.len() && fits(leaf + 1) {
+            leaf
+        } else if leaf > 0 && fits(leaf - 1) {
+            leaf - 1
+        } else {
+            return;
+        };
+
+        let second = self.leaves.remove(first + 1);
+        self.leaves[first].extend(second);
+    }
+
+    // Remove a range of lines and return them
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> Vec<String> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len
+        };
+
+        (start..end).map(|_| self.remove(start)).collect()
+    }
+
+    // Iterate over the lines in order
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.leaves.iter().flatten()
+    }
+
+    // Iterate over the lines starting at an index
+    pub fn iter_from(&self, index: usize) -> impl Iterator<Item = &String> {
+        let (leaf, offset) = self.locate(index.min(self.len));
+
+        self.leaves[leaf..].iter().flatten().skip(offset)
+    }
+
+}
+
+impl From<Vec<String>> for Rope {
+    fn from(lines: Vec<String>) -> Self {
+        let len = lines.len();
+        let mut leaves: Vec<Vec<String>> = Vec::with_capacity(len / LEAF_SIZE + 1);
+        let mut lines = lines.into_iter().peekable();
+
+        while lines.peek().is_some() {
+            leaves.push(lines.by_ref().take(LEAF_SIZE).collect());
+        }
+
+        if leaves.is_empty() {
+            leaves.push(Vec::new());
+        }
+
+        Self { leaves, len }
+    }
+}
+
+impl Index<usize> for Rope {
+    type Output = String;
+
+    fn index(&self, index: usize) -> &String {
+        match self.get(index) {
+            Some(line) => line,
+            None => panic!("line index {} out of bounds ({} lines)", index, self.len)
+        }
+    }
+}
+
+impl IndexMut<usize> for Rope {
+    fn index_mut(&mut self, index: usize) -> &mut String {
+        let len = self.len;
+        match self.get_mut(index) {
+            Some(line) => line,
+            None => panic!("line index {} out of bounds ({} lines)", index, len)
+        }
+    }
+}
+
+impl<T: AsRef<str>> PartialEq<Vec<T>> for Rope {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        self.len == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b.as_ref())
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Rope {
+        Rope::from((0..count).map(|i| i.to_string()).collect::<Vec<_>>())
+    }
+
+    fn leaf_sizes(rope: &Rope) -> Vec<usize> {
+        rope.leaves.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn full_leaves_split() {
+        let mut rope = numbered(LEAF_SIZE);
+        assert_eq!(leaf_sizes(&rope), vec![LEAF_SIZE]);
+
+        rope.insert(LEAF_SIZE, String::from("last"));
+        assert_eq!(leaf_sizes(&rope), vec![LEAF_SIZE / 2, LEAF_SIZE / 2 + 1]);
+        assert_eq!(rope.len(), LEAF_SIZE + 1);
+        assert_eq!(rope[LEAF_SIZE], "last");
+        assert_eq!(rope[LEAF_SIZE / 2], (LEAF_SIZE / 2).to_string());
+    }
+
+    #[test]
+    fn lines_are_found_at_leaf_edges() {
+        let rope = numbered(LEAF_SIZE * 2 + 3);
+
+        assert_eq!(rope.locate(0), (0, 0));
+        assert_eq!(rope.locate(LEAF_SIZE - 1), (0, LEAF_SIZE - 1));
+        assert_eq!(rope.locate(LEAF_SIZE), (1, 0));
+        assert_eq!(rope.locate(LEAF_SIZE * 2 + 2), (2, 2));
+        assert_eq!(rope.locate(rope.len()), (2, 3));
+
+        for i in [0, LEAF_SIZE - 1, LEAF_SIZE, LEAF_SIZE * 2 - 1, LEAF_SIZE * 2 + 2] {
+            assert_eq!(rope[i], i.to_string());
+        }
+        assert!(rope.get(rope.len()).is_none());
+    }
+
+    #[test]
+    fn iteration_from_the_middle_of_a_leaf() {
+        let rope = numbered(LEAF_SIZE + 10);
+
+        let lines: Vec<&String> = rope.iter_from(LEAF_SIZE - 2).collect();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(*lines[0], (LEAF_SIZE - 2).to_string());
+        assert_eq!(*lines[2], LEAF_SIZE.to_string());
+
+        assert_eq!(rope.iter_from(5).next().unwrap(), "5");
+        assert_eq!(rope.iter_from(rope.len()).count(), 0);
+        assert_eq!(rope.iter_from(rope.len() + 5).count(), 0);
+    }
+
+    #[test]
+    fn drain_across_leaves() {
+        let mut rope = numbered(LEAF_SIZE * 3);
+
+        let drained = rope.drain(LEAF_SIZE - 1..=LEAF_SIZE * 2);
+        assert_eq!(drained.len(), LEAF_SIZE + 2);
+        assert_eq!(drained[0], (LEAF_SIZE - 1).to_string());
+        assert_eq!(rope.len(), LEAF_SIZE * 2 - 2);
+        assert_eq!(rope[LEAF_SIZE - 2], (LEAF_SIZE - 2).to_string());
+        assert_eq!(rope[LEAF_SIZE - 1], (LEAF_SIZE * 2 + 1).to_string());
+        assert_eq!(rope.iter().count(), rope.len());
+    }
+
+    #[test]
+    fn insert_and_remove_at_both_ends() {
+        let mut rope = numbered(LEAF_SIZE + 1);
+
+        rope.insert(0, String::from("first"));
+        rope.insert(rope.len(), String::from("last"));
+        assert_eq!(rope[0], "first");
+        assert_eq!(rope[rope.len() - 1], "last");
+
+        assert_eq!(rope.remove(0), "first");
+        assert_eq!(rope.remove(rope.len() - 1), "last");
+        assert_eq!(rope, numbered(LEAF_SIZE + 1).iter().collect::<Vec<_>>());
+
+        // Down to nothing and back
+        let mut rope = Rope::new();
+        assert_eq!(rope.remove(0), "");
+        assert_eq!(rope.len(), 0);
+        rope.insert(0, String::from("again"));
+        assert_eq!(rope, vec!["again"]);
+    }
+
+    #[test]
+    fn emptied_leaves_are_merged() {
+        let mut rope = numbered(LEAF_SIZE * 8);
+        rope.drain(10..LEAF_SIZE * 8 - 10);
+
+        assert_eq!(leaf_sizes(&rope), vec![20]);
+        assert_eq!(rope[9], "9");
+        assert_eq!(rope[10], (LEAF_SIZE * 8 - 10).to_string());
+    }
+}
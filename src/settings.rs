@@ -0,0 +1,21 @@
+use crate::palette::Palette;
+
+// Settings.
+// Options shared by every buffer, console
+// commands change them while the editor runs
+pub struct Settings {
+    pub soft_wrap: bool,    // Wrap long lines instead of scrolling sideways
+    pub palette: Palette    // Colours everything is drawn with
+}
+
+impl Settings {
+
+    // Settings constructor, with the defaults
+    pub fn new() -> Self {
+        Self {
+            soft_wrap: false,
+            palette: Palette::default()
+        }
+    }
+
+}
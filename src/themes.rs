@@ -0,0 +1,323 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::palette::{self, Palette};
+
+// User palettes.
+// Palettes shared as files, kept in a themes directory
+// ($XDG_CONFIG_HOME/whiskey/themes) and listed by '?p' next to the
+// built-in ones, which they replace when they take the same name.
+// The directory is checked again while the editor runs, changed
+// files are reloaded and the palette in use follows them.
+//
+// TOML ('.toml'), colours as '#rrggbb' or '#rrggbbaa':
+//
+//     name = "ocean"          # Name for '?p', the file name when left out
+//     base = "dark"           # Built-in palette the colours left out come from
+//     background = "#1b2b34"
+//     text = "#d8dee9"
+//     dim, gutter, line, cursor, selection, console_background,
+//     console_text, info, warning, error
+//
+//     [syntax]
+//     keyword, string, number, comment, function, type_name
+//
+// base16 schemes ('.yaml' / '.yml') with 'scheme' and 'base00' to 'base0F',
+// or the newer 'name' and 'palette' layout, are imported as they are.
+//
+// A file with a mistake in it is left out, what is wrong goes to the console
+
+// Theme file kinds, by extension
+const EXTENSIONS: [&str; 3] = ["toml", "yaml", "yml"];
+
+pub struct Themes {
+    directory: Option<PathBuf>,                     // Where theme files are looked for
+    pub palettes: Vec<Palette>,                     // Palettes loaded from them
+    stamps: Vec<(PathBuf, Option<SystemTime>)>      // Theme files and when they were modified, as of the last load
+}
+
+// Mistake in a theme file
+#[derive(Debug, PartialEq)]
+pub struct ThemeError {
+    pub file: String,
+    pub problem: String
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "theme {}: {}", self.file, self.problem)
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+impl Themes {
+
+    // No user palettes
+    pub fn new() -> Self {
+        Self {
+            directory: None,
+            palettes: Vec::new(),
+            stamps: Vec::new()
+        }
+    }
+
+    // User palettes from a directory, loaded by the first 'reload'
+    pub fn open(directory: PathBuf) -> Self {
+        Self {
+            directory: Some(directory),
+            ..Self::new()
+        }
+    }
+
+    // Where theme files are kept, in the user's config directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config| config.join("whiskey").join("themes"))
+    }
+
+    // Every palette, built-in ones first
+    pub fn all(&self) -> Vec<Palette> {
+        let mut palettes: Vec<Palette> = Palette::builtin()
+            .into_iter()
+            .filter(|builtin| !self.palettes.iter().any(|palette| palette.name == builtin.name))
+            .collect();
+
+        palettes.extend(self.palettes.iter().cloned());
+        palettes
+    }
+
+    // Palette with a given name
+    pub fn find(&self, name: &str) -> Option<Palette> {
+        self.all().into_iter().find(|palette| palette.name == name)
+    }
+
+    // Load the theme files again when any of them was added, removed or changed.
+    // None when nothing changed, else the mistakes found in them
+    pub fn reload(&mut self) -> Option<Vec<ThemeError>> {
+        let stamps = self.scan();
+        if stamps == self.stamps {
+            return None;
+        }
+
+        let mut palettes: Vec<Palette> = Vec::new();
+        let mut errors = Vec::new();
+
+        for (path, _) in &stamps {
+            let file = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let error = |problem: String| ThemeError { file: file.clone(), problem };
+
+            let loaded = fs::read_to_string(path)
+                .map_err(|e| error(e.to_string()))
+                .and_then(|text| load(path, &text).map_err(error));
+
+            match loaded {
+                Ok(palette) if palettes.iter().any(|other| other.name == palette.name) => {
+                    errors.push(error(format!("palette '{}' is already defined by another file", palette.name)));
+                }
+                Ok(palette) => palettes.push(palette),
+                Err(error) => errors.push(error)
+            }
+        }
+
+        self.palettes = palettes;
+        self.stamps = stamps;
+        Some(errors)
+    }
+
+    // Theme files in the directory, by name
+    fn scan(&self) -> Vec<(PathBuf, Option<SystemTime>)> {
+        let Some(entries) = self.directory.as_ref().and_then(|directory| fs::read_dir(directory).ok()) else {
+            return Vec::new();
+        };
+
+        let mut stamps: Vec<(PathBuf, Option<SystemTime>)> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| EXTENSIONS.iter().any(|known| extension == *known)))
+            .map(|path| {
+                let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+                (path, modified)
+            })
+            .collect();
+
+        stamps.sort();
+        stamps
+    }
+
+}
+
+// Read a palette from a theme file's text, the extension tells the format
+fn load(path: &Path, text: &str) -> Result<Palette, String> {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => load_toml(text, &stem),
+        _ => load_base16(text, &stem)
+    }
+}
+
+// TOML theme, on top of its base palette
+fn load_toml(text: &str, stem: &str) -> Result<Palette, String> {
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+
+    let base = match table.get("base") {
+        None => String::from("dark"),
+        Some(toml::Value::String(base)) => base.clone(),
+        Some(_) => return Err(String::from("'base' has to be a palette name"))
+    };
+    let mut palette = Palette::find(&base).ok_or_else(|| format!("no built-in palette named '{}' to start from", base))?;
+
+    palette.name = match table.get("name") {
+        None => stem.to_string(),
+        Some(toml::Value::String(name)) if !name.trim().is_empty() => name.trim().to_string(),
+        Some(_) => return Err(String::from("'name' has to be a non-empty string"))
+    };
+
+    for (key, value) in &table {
+        match (key.as_str(), value) {
+            ("name" | "base", _) => {}
+            ("syntax", toml::Value::Table(syntax)) => {
+                for (key, value) in syntax {
+                    let slot = palette.syntax.color_mut(key).ok_or_else(|| format!("unknown syntax colour 'syntax.{}'", key))?;
+                    *slot = toml_color(&format!("syntax.{}", key), value)?;
+                }
+            }
+            (key, value) => {
+                let slot = palette.color_mut(key).ok_or_else(|| format!("unknown colour '{}'", key))?;
+                *slot = toml_color(key, value)?;
+            }
+        }
+    }
+
+    Ok(palette)
+}
+
+// Colour of a TOML key
+fn toml_color(key: &str, value: &toml::Value) -> Result<macroquad::color::Color, String> {
+    value.as_str()
+        .and_then(palette::parse_color)
+        .ok_or_else(|| format!("'{}' = {} is not a colour, use \"#rrggbb\"", key, value))
+}
+
+// base16 scheme, mapped the way the base16 styling guide uses its colours
+fn load_base16(text: &str, stem: &str) -> Result<Palette, String> {
+    let scheme: serde_yaml_ng::Mapping = serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+    let text_of = |mapping: &serde_yaml_ng::Mapping, key: &str| mapping.get(key).and_then(|value| value.as_str()).map(str::to_string);
+
+    // Newer schemes keep the colours under 'palette'
+    let colors = match scheme.get("palette") {
+        Some(serde_yaml_ng::Value::Mapping(colors)) => colors,
+        Some(_) => return Err(String::from("'palette' has to hold the base00 to base0F colours")),
+        None => &scheme
+    };
+
+    let base = |n: usize| -> Result<macroquad::color::Color, String> {
+        let key = format!("base{:02X}", n);
+        let value = text_of(colors, &key).ok_or_else(|| format!("'{}' is missing", key))?;
+        palette::parse_color(&value).ok_or_else(|| format!("'{}': {} is not a colour", key, value))
+    };
+
+    let name = text_of(&scheme, "scheme")
+        .or_else(|| text_of(&scheme, "name"))
+        .filter(|name| !name.trim().is_empty())
+        .map_or_else(|| stem.to_string(), |name| name.trim().to_string());
+
+    Ok(Palette {
+        name,
+        background: base(0x00)?,
+        text: base(0x05)?,
+        dim: base(0x04)?,
+        gutter: base(0x03)?,
+        line: base(0x02)?,
+        cursor: base(0x05)?,
+        selection: base(0x02)?,
+        console_background: base(0x01)?,
+        console_text: base(0x05)?,
+        info: base(0x04)?,
+        warning: base(0x0A)?,
+        error: base(0x08)?,
+        syntax: palette::Syntax {
+            keyword: base(0x0E)?,
+            string: base(0x0B)?,
+            number: base(0x09)?,
+            comment: base(0x03)?,
+            function: base(0x0D)?,
+            type_name: base(0x0A)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::rgb;
+
+    const BASE16: &str = "scheme: \"Ocean\"\nauthor: \"someone\"\n\
+        base00: \"2b303b\"\nbase01: \"343d46\"\nbase02: \"4f5b66\"\nbase03: \"65737e\"\n\
+        base04: \"a7adba\"\nbase05: \"c0c5ce\"\nbase06: \"dfe1e8\"\nbase07: \"eff1f5\"\n\
+        base08: \"bf616a\"\nbase09: \"d08770\"\nbase0A: \"ebcb8b\"\nbase0B: \"a3be8c\"\n\
+        base0C: \"96b5b4\"\nbase0D: \"8fa1b3\"\nbase0E: \"b48ead\"\nbase0F: \"ab7967\"\n";
+
+    #[test]
+    fn toml_themes_start_from_their_base() {
+        let text = "base = \"light\"\nbackground = \"#101010\"\n[syntax]\nkeyword = \"#ff0000\"\n";
+        let palette = load(Path::new("mine.toml"), text).unwrap();
+
+        assert_eq!(palette.name, "mine");
+        assert_eq!(palette.background, rgb(0x101010));
+        assert_eq!(palette.syntax.keyword, rgb(0xff0000));
+        assert_eq!(palette.text, Palette::find("light").unwrap().text);
+    }
+
+    #[test]
+    fn toml_mistakes_are_explained() {
+        let load = |text: &str| load(Path::new("mine.toml"), text).unwrap_err();
+
+        assert_eq!(load("backgroud = \"#101010\""), "unknown colour 'backgroud'");
+        assert_eq!(load("text = \"#1010\""), "'text' = \"#1010\" is not a colour, use \"#rrggbb\"");
+        assert_eq!(load("[syntax]\nkeyword = 3"), "'syntax.keyword' = 3 is not a colour, use \"#rrggbb\"");
+        assert_eq!(load("base = \"neon\""), "no built-in palette named 'neon' to start from");
+        assert!(!load("text = ").is_empty());
+    }
+
+    #[test]
+    fn base16_schemes_are_imported() {
+        let palette = load(Path::new("ocean.yaml"), BASE16).unwrap();
+        assert_eq!(palette.name, "Ocean");
+        assert_eq!(palette.background, rgb(0x2b303b));
+        assert_eq!(palette.syntax.string, rgb(0xa3be8c));
+
+        let nested = format!("name: \"Ocean 2\"\npalette:\n{}", BASE16.lines().skip(2).map(|line| format!("  {}\n", line)).collect::<String>());
+        assert_eq!(load(Path::new("ocean.yml"), &nested).unwrap().background, rgb(0x2b303b));
+
+        assert_eq!(load(Path::new("ocean.yaml"), "scheme: \"Broken\"\n").unwrap_err(), "'base00' is missing");
+    }
+
+    #[test]
+    fn changed_files_are_reloaded() {
+        let directory = std::env::temp_dir().join(format!("whiskey-themes-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("ocean.yaml"), BASE16).unwrap();
+        fs::write(directory.join("dark.toml"), "background = \"#101010\"").unwrap();
+        fs::write(directory.join("broken.toml"), "text = \"white\"").unwrap();
+        fs::write(directory.join("notes.txt"), "not a theme").unwrap();
+
+        let mut themes = Themes::open(directory.clone());
+        let errors = themes.reload().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, "broken.toml");
+        assert!(themes.reload().is_none());
+
+        // User palettes replace built-in ones with the same name
+        let names: Vec<String> = themes.all().into_iter().map(|palette| palette.name).collect();
+        assert_eq!(names, vec!["light", "solarized", "gruvbox", "dark", "Ocean"]);
+        assert_eq!(themes.find("dark").unwrap().background, rgb(0x101010));
+
+        fs::remove_file(directory.join("broken.toml")).unwrap();
+        assert_eq!(themes.reload(), Some(Vec::new()));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
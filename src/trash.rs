@@ -0,0 +1,196 @@
+use chrono::{DateTime, Local, TimeZone};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::files::{self, FileError};
+
+// Trash can.
+// Files deleted with '?rf' are moved into the home trash of the
+// freedesktop trash spec ($XDG_DATA_HOME/Trash) instead of being
+// unlinked, so file managers can list and restore them as well.
+// Each file goes into 'files/', with a '.trashinfo' entry in 'info/'
+// holding where it came from and when it was deleted
+pub struct Trash {
+    files: PathBuf,
+    info: PathBuf
+}
+
+// A file in the trash, enough to put it back
+#[derive(Debug)]
+pub struct Trashed {
+    pub original: PathBuf,  // Where the file was
+    file: PathBuf,          // Where it is now, inside 'files/'
+    info: PathBuf           // Its '.trashinfo' entry
+}
+
+impl Trash {
+
+    // The home trash, None without a data directory
+    pub fn home() -> Option<Self> {
+        dirs::data_dir().map(|data| Self::at(&data.join("Trash")))
+    }
+
+    // A trash can in a given directory
+    pub fn at(directory: &Path) -> Self {
+        Self {
+            files: directory.join("files"),
+            info: directory.join("info")
+        }
+    }
+
+    // Move a file into the trash
+    pub fn put(&self, path: &Path) -> Result<Trashed, FileError> {
+        let original = files::absolute(path);
+        fs::symlink_metadata(&original).map_err(|e| FileError::from_io(&original, e))?;
+
+        for directory in [&self.files, &self.info] {
+            fs::create_dir_all(directory).map_err(|e| FileError::from_io(directory, e))?;
+        }
+
+        let name = original.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let (file, info, mut entry) = self.claim(&name)?;
+
+        let content = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", encode(&original), timestamp(&Local::now()));
+        let moved = entry.write_all(content.as_bytes())
+            .and_then(|()| move_file(&original, &file));
+
+        if let Err(error) = moved {
+            let _ = fs::remove_file(&info);
+            return Err(FileError::from_io(&original, error));
+        }
+
+        Ok(Trashed { original, file, info })
+    }
+
+    // Reserve a name inside the trash by creating its info entry,
+    // 'name', then 'name.2', 'name.3'... when it is taken
+    fn claim(&self, name: &str) -> Result<(PathBuf, PathBuf, fs::File), FileError> {
+        let mut n = 1;
+        loop {
+            let name = if n == 1 { name.to_string() } else { format!("{}.{}", name, n) };
+            let file = self.files.join(&name);
+            let info = self.info.join(format!("{}.trashinfo", name));
+            n += 1;
+
+            if file.exists() {
+                continue;
+            }
+
+            match fs::OpenOptions::new().write(true).create_new(true).open(&info) {
+                Ok(entry) => return Ok((file, info, entry)),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(FileError::from_io(&info, error))
+            }
+        }
+    }
+
+}
+
+impl Trashed {
+
+    // Put the file back where it was, never over another file
+    pub fn restore(&self) -> Result<(), FileError> {
+        if fs::symlink_metadata(&self.original).is_ok() {
+            let error = io::Error::new(io::ErrorKind::AlreadyExists, "a file is in the way, not restoring over it");
+            return Err(FileError::from_io(&self.original, error));
+        }
+
+        move_file(&self.file, &self.original).map_err(|e| FileError::from_io(&self.file, e))?;
+        let _ = fs::remove_file(&self.info);
+
+        Ok(())
+    }
+
+}
+
+// Rename a file, copying it over when
+// the two places are on different devices
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result
+    }
+}
+
+// Percent-encode a path for the 'Path=' key,
+// keeping the separators and unreserved characters
+fn encode(path: &Path) -> String {
+    path.to_string_lossy()
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+// 'YYYY-MM-DDThh:mm:ss', in the time zone of the time given
+// (the spec wants the user's local time, without the offset)
+fn timestamp<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display
+{
+    time.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_deletion_dates() {
+        use chrono::{FixedOffset, Utc};
+
+        let time = Utc.with_ymd_and_hms(2000, 2, 29, 23, 1, 1).unwrap();
+        assert_eq!(timestamp(&time), "2000-02-29T23:01:01");
+
+        // Local time, not UTC
+        let paris = time.with_timezone(&FixedOffset::east_opt(3600).unwrap());
+        assert_eq!(timestamp(&paris), "2000-03-01T00:01:01");
+    }
+
+    #[test]
+    fn encodes_paths() {
+        assert_eq!(encode(Path::new("/home/me/my notes.txt")), "/home/me/my%20notes.txt");
+        assert_eq!(encode(Path::new("/tmp/é")), "/tmp/%C3%A9");
+    }
+
+    #[test]
+    fn trashes_and_restores() {
+        let directory = std::env::temp_dir().join(format!("whiskey-trash-{}", std::process::id()));
+        let trash = Trash::at(&directory.join("Trash"));
+        let path = directory.join("notes.txt");
+        fs::create_dir_all(&directory).unwrap();
+
+        fs::write(&path, "first").unwrap();
+        let first = trash.put(&path).unwrap();
+        assert!(!path.exists());
+
+        let info = fs::read_to_string(directory.join("Trash/info/notes.txt.trashinfo")).unwrap();
+        assert!(info.starts_with(&format!("[Trash Info]\nPath={}\nDeletionDate=", encode(&path))));
+
+        // Same name again goes next to it
+        fs::write(&path, "second").unwrap();
+        let second = trash.put(&path).unwrap();
+        assert!(directory.join("Trash/files/notes.txt.2").exists());
+
+        // Never over another file
+        fs::write(&path, "third").unwrap();
+        assert!(second.restore().is_err());
+        fs::remove_file(&path).unwrap();
+
+        second.restore().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert!(!directory.join("Trash/info/notes.txt.2.trashinfo").exists());
+
+        fs::remove_file(&path).unwrap();
+        first.restore().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+// Viewport.
+// The part of the file shown on screen, each editor keeps
+// its own so switching buffers keeps where you were.
+//
+// The viewport follows the cursor, keeping 'margin' lines
+// visible above and below it, but only when the cursor moved
+// to another line, so scrolling with the mouse wheel can look
+// around without jumping back to the cursor every frame.
+// Editing the text brings the cursor back on screen.
+//
+// Long lines scroll horizontally the same way, by pixels
+// since the text width is measured with the font
+pub struct Viewport {
+    pub top: usize,                         // First visible line
+    pub left: f32,                          // Pixels scrolled to the right
+    pub rows: usize,                        // Rows on screen as of the last frame, for paging
+    followed: Option<usize>,                // Cursor line the viewport last followed
+    followed_x: Option<(usize, usize)>,     // Cursor position the horizontal scroll last followed
+    followed_row: Option<(usize, usize)>    // Cursor position the wrapped rows last followed
+}
+
+impl Viewport {
+
+    // Viewport constructor, at the top of the file
+    pub fn new() -> Self {
+        Self {
+            top: 0,
+            left: 0.0,
+            rows: 0,
+            followed: None,
+            followed_x: None,
+            followed_row: None
+        }
+    }
+
+    // Scroll so the cursor line is on screen with
+    // 'margin' lines around it, if the cursor moved
+    pub fn follow(&mut self, cursor_y: usize, rows: usize, margin: usize, lines: usize) {
+        if self.followed == Some(cursor_y) {
+            return;
+        }
+        self.followed = Some(cursor_y);
+
+        // Small windows can't fit the margin on both sides
+        let margin = margin.min(rows.saturating_sub(1) / 2);
+
+        if cursor_y < self.top + margin {
+            self.top = cursor_y.saturating_sub(margin);
+        } else if cursor_y + margin >= self.top + rows {
+            self.top = (cursor_y + margin + 1).saturating_sub(rows);
+        }
+
+        self.clamp(rows, lines);
+    }
+
+    // Follow the cursor on the next frame even if it didn't move,
+    // the text changed under it
+    pub fn refollow(&mut self) {
+        self.followed = None;
+        self.followed_x = None;
+        self.followed_row = None;
+    }
+
+    // Scroll so a line is in the middle of the screen
+    pub fn center(&mut self, line: usize, lines: usize) {
+        let rows = self.rows.max(1);
+        self.top = line.saturating_sub(rows / 2);
+        self.clamp(rows, lines);
+    }
+
+    // With soft wrapping, lines above the cursor can take several rows.
+    // Scroll down until the cursor's row, given by 'cursor_row' for a top line,
+    // is on screen with 'margin' rows below it, if the cursor moved
+    pub fn follow_wrapped(&mut self, cursor: (usize, usize), rows: usize, margin: usize, cursor_row: impl Fn(usize) -> Option<usize>) {
+        if self.followed_row == Some(cursor) {
+            return;
+        }
+        self.followed_row = Some(cursor);
+
+        let margin = margin.min(rows.saturating_sub(1) / 2);
+
+        while self.top < cursor.0 && cursor_row(self.top).is_none_or(|row| row + margin >= rows) {
+            self.top += 1;
+        }
+    }
+
+    // Scroll sideways so the caret, 'caret' pixels into its line,
+    // is on screen with 'margin' pixels around it, if the cursor moved
+    pub fn follow_x(&mut self, cursor: (usize, usize), caret: f32, width: f32, margin: f32) {
+        if self.followed_x == Some(cursor) {
+            return;
+        }
+        self.followed_x = Some(cursor);
+
+        let margin = margin.min(width / 2.0);
+
+        if caret < self.left + margin {
+            self.left = caret - margin;
+        } else if caret > self.left + width - margin {
+            self.left = caret - width + margin;
+        }
+
+        self.left = self.left.max(0.0);
+    }
+
+    // Scroll sideways by some pixels, left when negative
+    pub fn scroll_x(&mut self, delta: f32) {
+        self.left = (self.left + delta).max(0.0);
+    }
+
+    // Scroll by a number of lines, up when negative
+    pub fn scroll(&mut self, delta: isize, rows: usize, lines: usize) {
+        self.top = self.top.saturating_add_signed(delta);
+        self.clamp(rows, lines);
+    }
+
+    // Don't scroll past the end, the last line
+    // stays at the bottom of the screen at most
+    fn clamp(&mut self, rows: usize, lines: usize) {
+        self.top = self.top.min(lines.saturating_sub(rows));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_keeps_a_margin_around_the_cursor() {
+        let mut viewport = Viewport::new();
+
+        viewport.follow(20, 10, 3, 100);
+        assert_eq!(viewport.top, 14);
+        viewport.follow(15, 10, 3, 100);
+        assert_eq!(viewport.top, 12);
+
+        // Moving inside the screen doesn't scroll
+        viewport.follow(17, 10, 3, 100);
+        assert_eq!(viewport.top, 12);
+
+        // Not past the end of the file
+        viewport.follow(99, 10, 3, 100);
+        assert_eq!(viewport.top, 90);
+
+        // Tiny windows still show the cursor
+        viewport.follow(50, 2, 3, 100);
+        assert_eq!(viewport.top, 50);
+    }
+
+    #[test]
+    fn scrolling_away_sticks_until_the_text_changes() {
+        let mut viewport = Viewport::new();
+        viewport.follow(5, 10, 3, 100);
+        assert_eq!(viewport.top, 0);
+
+        viewport.scroll(40, 10, 100);
+        viewport.follow(5, 10, 3, 100);
+        assert_eq!(viewport.top, 40);
+
+        viewport.refollow();
+        viewport.follow(5, 10, 3, 100);
+        assert_eq!(viewport.top, 2);
+    }
+
+    #[test]
+    fn clamp_stops_at_the_end() {
+        let mut viewport = Viewport::new();
+
+        viewport.scroll(500, 10, 100);
+        assert_eq!(viewport.top, 90);
+        viewport.scroll(-500, 10, 100);
+        assert_eq!(viewport.top, 0);
+
+        // Files shorter than the screen don't scroll
+        viewport.top = 3;
+        viewport.clamp(10, 5);
+        assert_eq!(viewport.top, 0);
+    }
+
+    #[test]
+    fn follow_x_keeps_the_caret_in_view() {
+        let mut viewport = Viewport::new();
+
+        viewport.follow_x((0, 80), 800.0, 500.0, 50.0);
+        assert_eq!(viewport.left, 350.0);
+        viewport.follow_x((0, 60), 600.0, 500.0, 50.0);
+        assert_eq!(viewport.left, 350.0);
+
+        viewport.follow_x((0, 10), 100.0, 500.0, 50.0);
+        assert_eq!(viewport.left, 50.0);
+        viewport.follow_x((0, 0), 0.0, 500.0, 50.0);
+        assert_eq!(viewport.left, 0.0);
+
+        // Same position, scrolled away by hand: stays
+        viewport.scroll_x(300.0);
+        viewport.follow_x((0, 0), 0.0, 500.0, 50.0);
+        assert_eq!(viewport.left, 300.0);
+    }
+}